@@ -1,7 +1,11 @@
 CFLAGS	= -std=gnu99 -Os -Wall -Wextra -nostdinc -fno-builtin -ffreestanding \
 	  -m32 -fno-asynchronous-unwind-tables -fno-common -static
+# Frame pointers are followed by the kernel to print backtraces
+CFLAGS	+= -fno-omit-frame-pointer
 # SSP causes compilation problems on Ubuntu
 CFLAGS	+= -fno-stack-protector
+# The ROMs and their libraries are loaded anywhere by the kernel
+CFLAGS	+= -fpie
 K_EXTRA_CFLAGS = -g3
 #CFLAGS += -ffunction-sections -fdata-sections
 CPPFLAGS += -I$(shell $(CC) -m32 --print-file-name=include)
@@ -0,0 +1,198 @@
+//! Dynamic section of position independent executables, and their relocations
+//!
+//! The tables are read from the loaded image, as the dynamic section gives their
+//! virtual addresses.
+
+use core::mem::size_of;
+use core::ptr;
+
+use super::{Elf32Symbol, Error};
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+const DT_NULL: i32 = 0;
+const DT_PLTRELSZ: i32 = 2;
+const DT_SYMTAB: i32 = 6;
+const DT_RELA: i32 = 7;
+const DT_SYMENT: i32 = 11;
+const DT_REL: i32 = 17;
+const DT_RELSZ: i32 = 18;
+const DT_RELENT: i32 = 19;
+const DT_JMPREL: i32 = 23;
+
+const R_386_NONE: u8 = 0;
+const R_386_32: u8 = 1;
+const R_386_RELATIVE: u8 = 8;
+
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xFFF1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf32Dyn {
+    tag: i32,
+    val: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Elf32Rel {
+    offset: u32,
+    info: u32,
+}
+
+impl Elf32Rel {
+    fn typ(&self) -> u8 {
+        self.info as u8
+    }
+
+    fn symbol(&self) -> usize {
+        (self.info >> 8) as usize
+    }
+}
+
+/// Segments loaded in memory
+pub(super) struct Image<'a> {
+    memory: &'a mut [u8],
+    /// Virtual address of the beginning of the memory
+    start: usize,
+    /// Difference between the addresses in memory and the virtual ones
+    bias: usize,
+}
+
+impl<'a> Image<'a> {
+    pub fn new(memory: &'a mut [u8], start: usize, bias: usize) -> Self {
+        Image {
+            memory,
+            start,
+            bias,
+        }
+    }
+
+    /// The tables are not aligned in the memory
+    fn read<T: Copy>(&self, vaddr: usize) -> Result<T> {
+        let bytes = self.bytes(vaddr, size_of::<T>())?;
+        Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+
+    fn write(&mut self, vaddr: usize, value: u32) -> Result<()> {
+        let offset = self.offset(vaddr, size_of::<u32>())?;
+        self.memory[offset..offset + size_of::<u32>()].copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    fn bytes(&self, vaddr: usize, size: usize) -> Result<&[u8]> {
+        let offset = self.offset(vaddr, size)?;
+        Ok(&self.memory[offset..offset + size])
+    }
+
+    fn offset(&self, vaddr: usize, size: usize) -> Result<usize> {
+        let offset = vaddr.checked_sub(self.start).ok_or(Error::OutOfBounds)?;
+        match offset.checked_add(size) {
+            Some(end) if end <= self.memory.len() => Ok(offset),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+/// Tables found in the dynamic section
+#[derive(Default)]
+struct Dynamic {
+    rel: usize,
+    rel_size: usize,
+    rel_entry: usize,
+    jmp_rel: usize,
+    jmp_rel_size: usize,
+    symtab: usize,
+    sym_entry: usize,
+}
+
+impl Dynamic {
+    fn read(image: &Image, vaddr: usize, size: usize) -> Result<Self> {
+        let mut dynamic = Dynamic {
+            rel_entry: size_of::<Elf32Rel>(),
+            sym_entry: size_of::<Elf32Symbol>(),
+            ..Dynamic::default()
+        };
+
+        for index in 0..size / size_of::<Elf32Dyn>() {
+            let entry: Elf32Dyn = image.read(vaddr.wrapping_add(index * size_of::<Elf32Dyn>()))?;
+            let value = entry.val as usize;
+
+            match entry.tag {
+                DT_NULL => break,
+                // The i386 ABI only uses implicit addends
+                DT_RELA => return Err(Error::UnknownElf),
+                DT_REL => dynamic.rel = value,
+                DT_RELSZ => dynamic.rel_size = value,
+                DT_RELENT => dynamic.rel_entry = value,
+                DT_JMPREL => dynamic.jmp_rel = value,
+                DT_PLTRELSZ => dynamic.jmp_rel_size = value,
+                DT_SYMTAB => dynamic.symtab = value,
+                DT_SYMENT => dynamic.sym_entry = value,
+                _ => {}
+            }
+        }
+
+        if dynamic.rel_entry < size_of::<Elf32Rel>() || dynamic.sym_entry < size_of::<Elf32Symbol>()
+        {
+            return Err(Error::UnknownElf);
+        }
+
+        Ok(dynamic)
+    }
+
+    fn symbol(&self, image: &Image, index: usize) -> Result<Elf32Symbol> {
+        index
+            .checked_mul(self.sym_entry)
+            .and_then(|offset| self.symtab.checked_add(offset))
+            .ok_or(Error::OutOfBounds)
+            .and_then(|vaddr| image.read(vaddr))
+    }
+}
+
+/// Apply the relocations listed by the dynamic section, at `vaddr`
+pub(super) fn relocate(image: &mut Image, vaddr: usize, size: usize) -> Result<()> {
+    let dynamic = Dynamic::read(image, vaddr, size)?;
+
+    for &(table, size) in &[
+        (dynamic.rel, dynamic.rel_size),
+        (dynamic.jmp_rel, dynamic.jmp_rel_size),
+    ] {
+        for index in 0..size / dynamic.rel_entry {
+            let relocation: Elf32Rel = image.read(table.wrapping_add(index * dynamic.rel_entry))?;
+            apply(image, &dynamic, relocation)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn apply(image: &mut Image, dynamic: &Dynamic, relocation: Elf32Rel) -> Result<()> {
+    let location = relocation.offset as usize;
+
+    let value = match relocation.typ() {
+        R_386_NONE => return Ok(()),
+        // B + A
+        R_386_RELATIVE => image.read::<u32>(location)?.wrapping_add(image.bias as u32),
+        // S + A
+        R_386_32 => {
+            let address = match relocation.symbol() {
+                // The null symbol stands for the address 0
+                0 => 0,
+                index => {
+                    let symbol = dynamic.symbol(image, index)?;
+                    match symbol.shndx {
+                        SHN_UNDEF => return Err(Error::UndefinedSymbol),
+                        SHN_ABS => symbol.value,
+                        _ => symbol.value.wrapping_add(image.bias as u32),
+                    }
+                }
+            };
+            image.read::<u32>(location)?.wrapping_add(address)
+        }
+        typ => return Err(Error::UnknownRelocation(typ)),
+    };
+
+    image.write(location, value)
+}
@@ -1,49 +1,75 @@
 use core::marker::PhantomData;
+use core::mem::size_of;
 
 use no_std_io::{Read, Seek, SeekFrom};
 
-use super::{read_struct, Elf};
+use super::{read_struct, Elf, Error, Result};
 
-pub(super) struct ElfProgramHeaderIterator<'a, R, P>
+/// Iterator over a table of headers, the program headers or the section headers
+///
+/// It stops after the first error.
+pub(super) struct ElfHeaderIterator<'a, R, P>
 where
     R: Read + Seek,
 {
     elf: &'a mut Elf<R>,
+    /// Offset of the table in the file
+    offset: usize,
     /// Current entry number
-    entry: u16,
+    entry: usize,
+    /// Number of entries in the table
+    count: usize,
     _marker: PhantomData<P>,
 }
 
-impl<'a, R, P> ElfProgramHeaderIterator<'a, R, P>
+impl<'a, R, P> ElfHeaderIterator<'a, R, P>
 where
     R: Read + Seek,
 {
-    pub fn new(elf: &'a mut Elf<R>) -> ElfProgramHeaderIterator<'a, R, P> {
-        // TODO Do not unwrap ? Maybe use allocation later.
-        elf.reader
-            .seek(SeekFrom::Start(elf.header.phoff as usize))
-            .unwrap();
-
-        ElfProgramHeaderIterator {
+    pub fn new(elf: &'a mut Elf<R>, offset: u32, count: u16) -> ElfHeaderIterator<'a, R, P> {
+        ElfHeaderIterator {
             elf,
+            offset: offset as usize,
             entry: 0,
-            _marker: PhantomData::default(),
+            count: count as usize,
+            _marker: PhantomData,
         }
     }
+
+    fn read(&mut self) -> Result<P> {
+        // Seek each time, as the reader may be used between two entries
+        let offset = self
+            .entry
+            .checked_mul(size_of::<P>())
+            .and_then(|offset| offset.checked_add(self.offset))
+            .ok_or(Error::OutOfBounds)?;
+        self.elf
+            .reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(Error::Io)?;
+
+        read_struct(&mut self.elf.reader)
+    }
 }
 
-impl<'a, R, P> Iterator for ElfProgramHeaderIterator<'a, R, P>
+impl<'a, R, P> Iterator for ElfHeaderIterator<'a, R, P>
 where
     R: Read + Seek,
 {
-    type Item = P;
+    type Item = Result<P>;
 
-    fn next(&mut self) -> Option<P> {
-        if self.entry == self.elf.header.phnum {
+    fn next(&mut self) -> Option<Result<P>> {
+        if self.entry >= self.count {
             return None;
         }
 
-        self.entry += 1;
-        Some(read_struct(&mut self.elf.reader).unwrap())
+        let header = self.read();
+        self.entry = if header.is_ok() {
+            self.entry + 1
+        } else {
+            self.count
+        };
+
+        Some(header)
     }
 }
@@ -1,25 +1,33 @@
 #![cfg_attr(feature = "no_std", no_std)]
-#![feature(try_from)]
 
 extern crate no_std_io;
 
+mod dynamic;
 mod enums;
 mod iterators;
+mod notes;
+mod symbols;
+#[cfg(test)]
+mod tests;
 
 use core::convert::TryFrom;
 use core::fmt;
-use core::intrinsics::transmute;
-use core::mem::{size_of, uninitialized};
+use core::mem::{size_of, MaybeUninit};
 use core::slice;
+use core::str;
 
 use no_std_io::{Error as IoError, Read, Seek, SeekFrom};
 
 pub use self::enums::*;
+pub use self::notes::{Note, Notes};
+pub use self::symbols::SymbolTable;
 
 const ELFMAG: &[u8] = b"\x7FELF";
 const EI_NIDENT: usize = 16;
+/// Longest section name looked up by `Elf::find_section`
+const MAX_SECTION_NAME: usize = 64;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
     Io(IoError),
     /// The file is not an elf (e.g. wrong magic number / too small for the header)
@@ -28,6 +36,10 @@ pub enum Error {
     OutOfBounds,
     /// A value is unknown
     UnknownElf,
+    /// The type of a relocation is not supported
+    UnknownRelocation(u8),
+    /// A relocation refers to a symbol which is not in the file
+    UndefinedSymbol,
 }
 
 type Result<T> = ::core::result::Result<T, Error>;
@@ -52,38 +64,213 @@ where
         Elf { reader, header }.validate()
     }
 
-    pub fn program_headers<'a>(&'a mut self) -> impl 'a + Iterator<Item = impl ElfProgramHeader> {
-        iterators::ElfProgramHeaderIterator::<'a, R, Elf32ProgramHeader>::new(self)
+    pub fn program_headers<'a>(
+        &'a mut self,
+    ) -> impl 'a + Iterator<Item = Result<impl ElfProgramHeader>> {
+        let (offset, count) = (self.header.phoff, self.header.phnum);
+        iterators::ElfHeaderIterator::<'a, R, Elf32ProgramHeader>::new(self, offset, count)
+    }
+
+    pub fn section_headers<'a>(
+        &'a mut self,
+    ) -> impl 'a + Iterator<Item = Result<impl ElfSectionHeader>> {
+        let (offset, count) = (self.header.shoff, self.header.shnum);
+        iterators::ElfHeaderIterator::<'a, R, Elf32SectionHeader>::new(self, offset, count)
+    }
+
+    /// Section by index, such as the one linked to another section
+    pub fn section(&mut self, index: usize) -> Result<Elf32SectionHeader> {
+        if index >= self.header.shnum as usize {
+            return Err(Error::OutOfBounds);
+        }
+
+        // The table was checked to be in the file
+        let offset = self.header.shoff as usize + index * size_of::<Elf32SectionHeader>();
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(Error::Io)?;
+        read_struct(&mut self.reader)
+    }
+
+    /// Name of the section, from the section name string table
+    ///
+    /// The name must fit in the buffer, with its null terminator.
+    pub fn section_name<'b, S>(&mut self, section: &S, buffer: &'b mut [u8]) -> Result<&'b str>
+    where
+        S: ElfSectionHeader,
+    {
+        let names = self.section(self.header.shstrndx as usize)?;
+        let size = names
+            .size()
+            .checked_sub(section.name())
+            .ok_or(Error::OutOfBounds)?
+            .min(buffer.len());
+        let offset = names
+            .offset()
+            .checked_add(section.name())
+            .ok_or(Error::OutOfBounds)?;
+        self.read_at(offset, &mut buffer[..size])?;
+
+        let length = buffer[..size]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(Error::OutOfBounds)?;
+        str::from_utf8(&buffer[..length]).map_err(|_| Error::UnknownElf)
+    }
+
+    /// Find a section by name, such as `.text` or `.note.gnu.build-id`
+    pub fn find_section(&mut self, name: &str) -> Result<Option<Elf32SectionHeader>> {
+        if name.len() >= MAX_SECTION_NAME {
+            return Ok(None);
+        }
+
+        let mut buffer = [0; MAX_SECTION_NAME];
+        for index in 0..self.header.shnum as usize {
+            let section = self.section(index)?;
+            match self.section_name(&section, &mut buffer) {
+                Ok(section_name) if section_name == name => return Ok(Some(section)),
+                // Names too long to be the one searched for
+                Ok(_) | Err(Error::OutOfBounds) | Err(Error::UnknownElf) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The sections of the static symbol table (`.symtab`) and of its strings (`.strtab`)
+    pub fn symtab(&mut self) -> Result<Option<SymbolSections>> {
+        self.symbol_sections(SHT_SYMTAB)
+    }
+
+    /// The sections of the dynamic symbol table (`.dynsym`) and of its strings (`.dynstr`)
+    pub fn dynsym(&mut self) -> Result<Option<SymbolSections>> {
+        self.symbol_sections(SHT_DYNSYM)
+    }
+
+    fn symbol_sections(&mut self, typ: u32) -> Result<Option<SymbolSections>> {
+        for index in 0..self.header.shnum as usize {
+            let symbols = self.section(index)?;
+            if symbols.typ() == typ {
+                let strings = self.section(symbols.link())?;
+                if !strings.is_strtab() {
+                    return Err(Error::UnknownElf);
+                }
+
+                return Ok(Some(SymbolSections { symbols, strings }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Size of the content of a section in the file, checked against the size of the file
+    ///
+    /// It is 0 for the sections not in the file like `.bss`. Use it to allocate the
+    /// buffer given to `read_section`.
+    pub fn section_size<S>(&mut self, section: &S) -> Result<usize>
+    where
+        S: ElfSectionHeader,
+    {
+        if section.typ() == SHT_NOBITS {
+            return Ok(0);
+        }
+
+        let len = self.len()?;
+        match section.offset().checked_add(section.size()) {
+            Some(end) if end <= len => Ok(section.size()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
+    /// Read the content of a section, which must fit in the buffer
+    ///
+    /// Returns the size of the section, as given by `section_size`.
+    pub fn read_section<S>(&mut self, section: &S, buffer: &mut [u8]) -> Result<usize>
+    where
+        S: ElfSectionHeader,
+    {
+        let size = self.section_size(section)?;
+        let buffer = buffer.get_mut(..size).ok_or(Error::OutOfBounds)?;
+        self.read_at(section.offset(), buffer)?;
+        Ok(buffer.len())
+    }
+
+    /// Read the content of a segment in the file, which must fit in the buffer
+    ///
+    /// Returns the size of the segment in the file, the rest of it in memory is zeroed.
+    pub fn read_segment<P>(&mut self, segment: &P, buffer: &mut [u8]) -> Result<usize>
+    where
+        P: ElfProgramHeader,
+    {
+        let buffer = buffer
+            .get_mut(..segment.file_size())
+            .ok_or(Error::OutOfBounds)?;
+        self.read_at(segment.offset(), buffer)?;
+        Ok(buffer.len())
     }
 
     pub fn entry_point(&self) -> usize {
         self.header.entry as usize
     }
 
+    /// The executable can be loaded at any address, once relocated
+    pub fn is_position_independent(&self) -> bool {
+        self.header.typ == ET_DYN
+    }
+
+    /// Apply the relocations of the loaded segments
+    ///
+    /// `memory` holds the segments from the virtual address `start`, and `bias` is the
+    /// difference between their addresses in memory and their virtual ones.
+    pub fn relocate(&mut self, memory: &mut [u8], start: usize, bias: usize) -> Result<()> {
+        let mut dynamic = None;
+        for header in self.program_headers() {
+            let header = header?;
+            if header.typ() == PT_DYNAMIC {
+                dynamic = Some((header.vaddr(), header.file_size()));
+                break;
+            }
+        }
+
+        match dynamic {
+            Some((vaddr, size)) => {
+                dynamic::relocate(&mut dynamic::Image::new(memory, start, bias), vaddr, size)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Fill the buffer with the data at `offset` in the file
+    fn read_at(&mut self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(Error::Io)?;
+
+        match self.reader.read(buffer).map_err(Error::Io)? {
+            size if size == buffer.len() => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+
     fn validate(mut self) -> Result<Self> {
         self.len().and_then(|size| self.header.validate(size))?;
 
         Ok(self)
     }
 
-    /// Calculate the remaining size of the file from the current position
+    /// Size of the file, the position in it is kept
     fn len(&mut self) -> Result<usize> {
         // Save position
-        let current_pos = self
-            .reader
-            .seek(SeekFrom::Current(0))
-            .map_err(|e| Error::Io(e))?;
+        let current_pos = self.reader.seek(SeekFrom::Current(0)).map_err(Error::Io)?;
 
         // Read size of file
-        let size = self
-            .reader
-            .seek(SeekFrom::End(0))
-            .map_err(|e| Error::Io(e))?;
+        let size = self.reader.seek(SeekFrom::End(0)).map_err(Error::Io)?;
 
         // Restore position
         self.reader
-            .seek(SeekFrom::Current(current_pos as isize))
-            .map_err(|e| Error::Io(e))?;
+            .seek(SeekFrom::Start(current_pos))
+            .map_err(Error::Io)?;
 
         Ok(size)
     }
@@ -153,13 +340,23 @@ impl Elf32Header {
             return Err(Error::UnknownElf);
         }
 
+        if self.shnum != 0 && self.shentsize as usize != size_of::<Elf32SectionHeader>() {
+            return Err(Error::UnknownElf);
+        }
+
         // Program header table must be valid
-        if (self.phoff + (self.phnum * self.phentsize) as u32) as usize > len {
-            return Err(Error::OutOfBounds);
+        match table_end(self.phoff, self.phnum, self.phentsize) {
+            Some(end) if end <= len => {}
+            _ => return Err(Error::OutOfBounds),
         }
 
-        // Symbol header table must be valid
-        if (self.shoff + (self.shnum * self.shentsize) as u32) as usize > len {
+        // Section header table must be valid
+        match table_end(self.shoff, self.shnum, self.shentsize) {
+            Some(end) if end <= len => {}
+            _ => return Err(Error::OutOfBounds),
+        }
+
+        if self.shnum != 0 && self.shstrndx >= self.shnum {
             return Err(Error::OutOfBounds);
         }
 
@@ -167,7 +364,26 @@ impl Elf32Header {
     }
 }
 
+/// End of a table of `count` entries, or None if it overflows
+fn table_end(offset: u32, count: u16, entry_size: u16) -> Option<usize> {
+    (count as usize)
+        .checked_mul(entry_size as usize)?
+        .checked_add(offset as usize)
+}
+
+const ET_DYN: u16 = 3;
+
 const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOTE: u32 = 7;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
 
 const PF_X: u32 = 0b001;
 const PF_W: u32 = 0b010;
@@ -183,16 +399,21 @@ pub trait ElfProgramHeader: ::core::fmt::Debug {
     fn mem_size(&self) -> usize;
     fn align(&self) -> usize;
 
+    /// Segment to load in memory, the other ones only describe the file
     fn is_load(&self) -> bool {
-        self.typ() & PT_LOAD != 0
+        self.typ() == PT_LOAD
     }
 
-    fn is_data(&self) -> bool {
-        self.flags() == PF_X & PF_W & PF_R
+    fn is_readable(&self) -> bool {
+        self.flags() & PF_R != 0
     }
 
-    fn is_text(&self) -> bool {
-        self.flags() == PF_X & PF_R
+    fn is_writable(&self) -> bool {
+        self.flags() & PF_W != 0
+    }
+
+    fn is_executable(&self) -> bool {
+        self.flags() & PF_X != 0
     }
 }
 
@@ -244,11 +465,38 @@ impl ElfProgramHeader for Elf32ProgramHeader {
 }
 
 pub trait ElfSectionHeader {
+    /// Offset of the name in the section name string table
+    fn name(&self) -> usize;
+    fn typ(&self) -> u32;
+    fn flags(&self) -> u32;
     fn addr(&self) -> usize;
+    fn offset(&self) -> usize;
     fn size(&self) -> usize;
+    /// Index of the associated section, the string table of a symbol table
+    fn link(&self) -> usize;
+    fn info(&self) -> u32;
+    /// Size of the entries, for the sections holding a table
+    fn entry_size(&self) -> usize;
+
+    fn is_symtab(&self) -> bool {
+        self.typ() == SHT_SYMTAB
+    }
+
+    fn is_dynsym(&self) -> bool {
+        self.typ() == SHT_DYNSYM
+    }
+
+    fn is_strtab(&self) -> bool {
+        self.typ() == SHT_STRTAB
+    }
+
+    fn is_note(&self) -> bool {
+        self.typ() == SHT_NOTE
+    }
 }
 
 #[repr(C)]
+#[derive(Debug)]
 pub struct Elf32SectionHeader {
     name: u32,
     typ: u32,
@@ -263,29 +511,108 @@ pub struct Elf32SectionHeader {
 }
 
 impl ElfSectionHeader for Elf32SectionHeader {
+    fn name(&self) -> usize {
+        self.name as usize
+    }
+
+    fn typ(&self) -> u32 {
+        self.typ
+    }
+
+    fn flags(&self) -> u32 {
+        self.flags
+    }
+
     fn addr(&self) -> usize {
         self.addr as usize
     }
 
+    fn offset(&self) -> usize {
+        self.offset as usize
+    }
+
     fn size(&self) -> usize {
         self.size as usize
     }
+
+    fn link(&self) -> usize {
+        self.link as usize
+    }
+
+    fn info(&self) -> u32 {
+        self.info
+    }
+
+    fn entry_size(&self) -> usize {
+        self.entsize as usize
+    }
+}
+
+/// Sections of a symbol table and of the names of its symbols
+#[derive(Debug)]
+pub struct SymbolSections {
+    pub symbols: Elf32SectionHeader,
+    pub strings: Elf32SectionHeader,
+}
+
+/// Entry of a symbol table
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Elf32Symbol {
+    name: u32,
+    value: u32,
+    size: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+}
+
+impl Elf32Symbol {
+    /// Offset of the name in the associated string table
+    pub fn name(&self) -> usize {
+        self.name as usize
+    }
+
+    pub fn value(&self) -> usize {
+        self.value as usize
+    }
+
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Index of the section the symbol is defined in
+    pub fn section(&self) -> usize {
+        self.shndx as usize
+    }
+
+    pub fn typ(&self) -> u8 {
+        self.info & 0xF
+    }
+
+    pub fn binding(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn is_function(&self) -> bool {
+        self.typ() == STT_FUNC
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.typ() == STT_OBJECT
+    }
 }
 
-fn read_struct<'a, R, S>(reader: &mut R) -> Result<S>
+fn read_struct<R, S>(reader: &mut R) -> Result<S>
 where
     R: Read,
 {
-    let mut strct: S = unsafe { uninitialized() };
-
-    reader
-        .read(unsafe { slice::from_raw_parts_mut(transmute(&mut strct as *mut S), size_of::<S>()) })
-        .map_err(|io| Error::Io(io))
-        .and_then(|size| {
-            if size == size_of::<S>() {
-                Ok(strct)
-            } else {
-                Err(Error::OutOfBounds)
-            }
-        })
+    // The structures only hold integers, for which any value is valid
+    let mut strct = MaybeUninit::<S>::zeroed();
+    let bytes = unsafe { slice::from_raw_parts_mut(strct.as_mut_ptr() as *mut u8, size_of::<S>()) };
+
+    match reader.read(bytes).map_err(Error::Io)? {
+        size if size == size_of::<S>() => Ok(unsafe { strct.assume_init() }),
+        _ => Err(Error::OutOfBounds),
+    }
 }
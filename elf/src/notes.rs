@@ -0,0 +1,63 @@
+use core::convert::TryInto;
+use core::mem::size_of;
+
+/// Entry of a note section or segment
+#[derive(Clone, Copy, Debug)]
+pub struct Note<'a> {
+    /// Owner of the note, without its null terminator
+    pub name: &'a [u8],
+    pub typ: u32,
+    pub desc: &'a [u8],
+}
+
+/// Iterator over the entries of a note section or segment in memory
+///
+/// It stops at the first malformed entry.
+#[derive(Clone)]
+pub struct Notes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Notes<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Notes { data }
+    }
+
+    fn word(&self, index: usize) -> Option<usize> {
+        let bytes = self
+            .data
+            .get(index * size_of::<u32>()..(index + 1) * size_of::<u32>())?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    }
+}
+
+impl<'a> Iterator for Notes<'a> {
+    type Item = Note<'a>;
+
+    fn next(&mut self) -> Option<Note<'a>> {
+        let (name_size, desc_size, typ) = (self.word(0)?, self.word(1)?, self.word(2)?);
+
+        // The name and the descriptor are padded to 4 bytes
+        let name_start = 3 * size_of::<u32>();
+        let desc_start = name_start.checked_add(align4(name_size)?)?;
+        let end = desc_start.checked_add(align4(desc_size)?)?;
+        if end > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+
+        let name = &self.data[name_start..name_start + name_size];
+        let note = Note {
+            name: name.strip_suffix(&[0]).unwrap_or(name),
+            typ: typ as u32,
+            desc: &self.data[desc_start..desc_start + desc_size],
+        };
+        self.data = &self.data[end..];
+
+        Some(note)
+    }
+}
+
+fn align4(size: usize) -> Option<usize> {
+    Some(size.checked_add(3)? & !3)
+}
@@ -0,0 +1,53 @@
+use core::mem::size_of;
+use core::ptr;
+use core::str;
+
+use super::Elf32Symbol;
+
+/// Symbol table in memory, with its string table
+#[derive(Clone, Copy)]
+pub struct SymbolTable<'a> {
+    /// Entries of the table, which may not be aligned once read from a file
+    symbols: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new(symbols: &'a [u8], strings: &'a [u8]) -> Self {
+        SymbolTable { symbols, strings }
+    }
+
+    pub fn symbols(&self) -> impl 'a + Iterator<Item = Elf32Symbol> {
+        self.symbols
+            .chunks_exact(size_of::<Elf32Symbol>())
+            .map(|entry| unsafe { ptr::read_unaligned(entry.as_ptr() as *const Elf32Symbol) })
+    }
+
+    /// Returns None if the name is out of the string table, or is not valid UTF-8
+    pub fn name(&self, symbol: &Elf32Symbol) -> Option<&'a str> {
+        let bytes = self.strings.get(symbol.name()..)?;
+        let length = bytes.iter().position(|&b| b == 0)?;
+        str::from_utf8(&bytes[..length]).ok()
+    }
+
+    /// Function containing the address, and the offset of the address in it
+    pub fn lookup(&self, address: usize) -> Option<(Elf32Symbol, usize)> {
+        let mut best: Option<Elf32Symbol> = None;
+
+        for symbol in self.symbols() {
+            if !symbol.is_function() || address < symbol.value() {
+                continue;
+            }
+            // Symbols without size extend up to the next one
+            if symbol.size() != 0 && address - symbol.value() >= symbol.size() {
+                continue;
+            }
+
+            if best.map(|b| b.value() <= symbol.value()).unwrap_or(true) {
+                best = Some(symbol);
+            }
+        }
+
+        best.map(|symbol| (symbol, address - symbol.value()))
+    }
+}
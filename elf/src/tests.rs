@@ -0,0 +1,585 @@
+//! Host tests, on a small position independent executable built here and on malformed
+//! variants of it
+
+use no_std_io::{Read, Seek, SeekFrom};
+
+use super::*;
+
+/// Read-only file in memory
+#[derive(Clone)]
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Cursor { data, position: 0 }
+    }
+}
+
+impl<'a> Read for Cursor<'a> {
+    fn read(&mut self, buffer: &mut [u8]) -> no_std_io::Result<usize> {
+        let remaining = self.data.get(self.position..).unwrap_or(&[]);
+        let size = remaining.len().min(buffer.len());
+        buffer[..size].copy_from_slice(&remaining[..size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+impl<'a> Seek for Cursor<'a> {
+    fn seek(&mut self, from: SeekFrom) -> no_std_io::Result<usize> {
+        let position = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => add(self.position, offset),
+            SeekFrom::End(offset) => add(self.data.len(), offset),
+        };
+        self.position = position.ok_or(())?;
+        Ok(self.position)
+    }
+}
+
+fn add(position: usize, offset: isize) -> Option<usize> {
+    if offset < 0 {
+        position.checked_sub(offset.wrapping_neg() as usize)
+    } else {
+        position.checked_add(offset as usize)
+    }
+}
+
+// Layout of the executable. The code segment starts at the beginning of the file, and the
+// data segment is loaded at its offset plus DATA_BIAS.
+const PH_OFF: usize = 0x34;
+const PH_NUM: usize = 3;
+const TEXT: usize = 0x100;
+const ENTRY: usize = TEXT;
+const DYNSYM: usize = 0x140;
+const DYNSTR: usize = 0x180;
+const REL: usize = 0x1A0;
+const DATA_OFF: usize = 0x200;
+const DATA_BIAS: usize = 0x1000;
+const DATA_FILE_SIZE: usize = 0x40;
+const DATA_MEM_SIZE: usize = 0x80;
+/// Words relocated, in the data segment
+const RELATIVE_WORD: usize = DATA_OFF + 0x30;
+const ABSOLUTE_WORD: usize = DATA_OFF + 0x34;
+const SYMTAB: usize = 0x240;
+const STRTAB: usize = 0x280;
+const NOTE: usize = 0x2A0;
+const SHSTRTAB: usize = 0x2C0;
+const SH_OFF: usize = 0x340;
+const SH_NUM: usize = 12;
+const SHSTRNDX: usize = 11;
+const SIZE: usize = SH_OFF + SH_NUM * 40;
+/// Memory for the loaded segments, from the virtual address 0
+const MEMORY_SIZE: usize = DATA_OFF + DATA_BIAS + DATA_MEM_SIZE;
+
+const SECTION_NAMES: &[u8] =
+    b"\0.text\0.dynsym\0.dynstr\0.rel.dyn\0.dynamic\0.data\0.bss\0.symtab\0.strtab\0.note.test\0.shstrtab\0";
+
+struct Image(Vec<u8>);
+
+/// Change made to a valid executable, and the error it causes
+type Corruption = (fn(&mut Image), Error);
+
+impl Image {
+    fn put16(&mut self, offset: usize, value: u16) {
+        self.0[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put32(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put(&mut self, offset: usize, bytes: &[u8]) {
+        self.0[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn program_header(&mut self, index: usize, fields: [usize; 8]) {
+        for (i, &field) in fields.iter().enumerate() {
+            self.put32(PH_OFF + index * 32 + i * 4, field as u32);
+        }
+    }
+
+    fn section(&mut self, index: usize, name: &str, fields: [usize; 9]) {
+        let offset = SH_OFF + index * 40;
+        self.put32(offset, name_offset(name) as u32);
+        for (i, &field) in fields.iter().enumerate() {
+            self.put32(offset + 4 + i * 4, field as u32);
+        }
+    }
+
+    fn symbol(
+        &mut self,
+        offset: usize,
+        name: u32,
+        value: usize,
+        size: usize,
+        info: u8,
+        shndx: u16,
+    ) {
+        self.put32(offset, name);
+        self.put32(offset + 4, value as u32);
+        self.put32(offset + 8, size as u32);
+        self.0[offset + 12] = info;
+        self.put16(offset + 14, shndx);
+    }
+}
+
+fn name_offset(name: &str) -> usize {
+    if name.is_empty() {
+        return 0;
+    }
+
+    let mut pattern = Vec::from(name.as_bytes());
+    pattern.push(0);
+    SECTION_NAMES
+        .windows(pattern.len())
+        .position(|w| w == &pattern[..])
+        .expect("Unknown section name")
+}
+
+fn executable() -> Image {
+    let mut image = Image(vec![0; SIZE]);
+
+    image.put(0, b"\x7FELF\x01\x01\x01");
+    image.put16(16, 3); // ET_DYN
+    image.put16(18, 3); // EM_386
+    image.put32(20, 1);
+    image.put32(24, ENTRY as u32);
+    image.put32(28, PH_OFF as u32);
+    image.put32(32, SH_OFF as u32);
+    image.put16(40, 52);
+    image.put16(42, 32);
+    image.put16(44, PH_NUM as u16);
+    image.put16(46, 40);
+    image.put16(48, SH_NUM as u16);
+    image.put16(50, SHSTRNDX as u16);
+
+    let data = DATA_OFF + DATA_BIAS;
+    // typ, offset, vaddr, paddr, filesz, memsz, flags, align
+    image.program_header(0, [1, 0, 0, 0, DATA_OFF, DATA_OFF, 0b101, 0x1000]);
+    image.program_header(
+        1,
+        [
+            1,
+            DATA_OFF,
+            data,
+            data,
+            DATA_FILE_SIZE,
+            DATA_MEM_SIZE,
+            0b110,
+            0x1000,
+        ],
+    );
+    image.program_header(2, [2, DATA_OFF, data, data, 0x30, 0x30, 0b110, 4]);
+
+    image.put(TEXT, &[0x55, 0x89, 0xE5, 0x5D, 0xC3]);
+
+    // Dynamic symbols: the null one, then func
+    image.symbol(DYNSYM + 16, 1, TEXT + 0x10, 4, 0x12, 1);
+    image.put(DYNSTR, b"\0func\0");
+
+    // R_386_RELATIVE, then R_386_32 against func
+    image.put32(REL, (RELATIVE_WORD + DATA_BIAS) as u32);
+    image.put32(REL + 4, 8);
+    image.put32(REL + 8, (ABSOLUTE_WORD + DATA_BIAS) as u32);
+    image.put32(REL + 12, 1 << 8 | 1);
+
+    let dynamic: [(u32, usize); 6] = [(6, DYNSYM), (11, 16), (17, REL), (18, 16), (19, 8), (0, 0)];
+    for (i, &(tag, value)) in dynamic.iter().enumerate() {
+        image.put32(DATA_OFF + i * 8, tag);
+        image.put32(DATA_OFF + i * 8 + 4, value as u32);
+    }
+    image.put32(RELATIVE_WORD, 0x100);
+    image.put32(ABSOLUTE_WORD, 4);
+
+    // Static symbols: the null one, entry, other which has no size, and an object
+    image.symbol(SYMTAB + 16, 1, ENTRY, 8, 0x12, 1);
+    image.symbol(SYMTAB + 32, 7, ENTRY + 8, 0, 0x12, 1);
+    image.symbol(SYMTAB + 48, 13, DATA_OFF + DATA_BIAS + 0x38, 4, 0x11, 6);
+    image.put(STRTAB, b"\0entry\0other\0object\0");
+
+    // Note "test", of type 1, with 3 bytes of descriptor
+    image.put32(NOTE, 5);
+    image.put32(NOTE + 4, 3);
+    image.put32(NOTE + 8, 1);
+    image.put(NOTE + 12, b"test\0\0\0\0abc\0");
+
+    image.put(SHSTRTAB, SECTION_NAMES);
+
+    let bss = DATA_OFF + DATA_FILE_SIZE;
+    // typ, flags, addr, offset, size, link, info, addralign, entsize
+    image.section(0, "", [0; 9]);
+    image.section(1, ".text", [1, 6, TEXT, TEXT, 0x40, 0, 0, 16, 0]);
+    image.section(2, ".dynsym", [11, 2, DYNSYM, DYNSYM, 32, 3, 1, 4, 16]);
+    image.section(3, ".dynstr", [3, 2, DYNSTR, DYNSTR, 6, 0, 0, 1, 0]);
+    image.section(4, ".rel.dyn", [9, 2, REL, REL, 16, 2, 0, 4, 8]);
+    image.section(
+        5,
+        ".dynamic",
+        [6, 3, DATA_OFF + DATA_BIAS, DATA_OFF, 0x30, 3, 0, 4, 8],
+    );
+    image.section(
+        6,
+        ".data",
+        [
+            1,
+            3,
+            RELATIVE_WORD + DATA_BIAS,
+            RELATIVE_WORD,
+            0x10,
+            0,
+            0,
+            4,
+            0,
+        ],
+    );
+    image.section(7, ".bss", [8, 3, bss + DATA_BIAS, bss, 0x40, 0, 0, 4, 0]);
+    image.section(8, ".symtab", [2, 0, 0, SYMTAB, 64, 9, 1, 4, 16]);
+    image.section(9, ".strtab", [3, 0, 0, STRTAB, 20, 0, 0, 1, 0]);
+    image.section(10, ".note.test", [7, 2, NOTE, NOTE, 24, 0, 0, 4, 0]);
+    image.section(
+        11,
+        ".shstrtab",
+        [3, 0, 0, SHSTRTAB, SECTION_NAMES.len(), 0, 0, 1, 0],
+    );
+
+    image
+}
+
+fn open(data: &[u8]) -> Result<Elf<Cursor<'_>>> {
+    Elf::new(Cursor::new(data))
+}
+
+/// Load the segments in memory, as the kernel does
+fn load(elf: &mut Elf<Cursor>, memory: &mut [u8]) -> Result<()> {
+    let segments: Vec<_> = elf.program_headers().collect::<Result<_>>()?;
+    for segment in segments.iter().filter(|s| s.is_load()) {
+        let end = segment.vaddr().checked_add(segment.mem_size());
+        let memory = end
+            .and_then(|end| memory.get_mut(segment.vaddr()..end))
+            .ok_or(Error::OutOfBounds)?;
+        elf.read_segment(segment, memory)?;
+    }
+
+    Ok(())
+}
+
+fn word(memory: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        memory[offset],
+        memory[offset + 1],
+        memory[offset + 2],
+        memory[offset + 3],
+    ])
+}
+
+fn section_names(elf: &mut Elf<Cursor>) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut buffer = [0; 64];
+    for index in 0..SH_NUM {
+        let section = elf.section(index)?;
+        names.push(String::from(elf.section_name(&section, &mut buffer)?));
+    }
+
+    Ok(names)
+}
+
+fn symbol_table(elf: &mut Elf<Cursor>, sections: SymbolSections) -> (Vec<u8>, Vec<u8>) {
+    let mut symbols = vec![0; elf.section_size(&sections.symbols).unwrap()];
+    let mut strings = vec![0; elf.section_size(&sections.strings).unwrap()];
+    elf.read_section(&sections.symbols, &mut symbols).unwrap();
+    elf.read_section(&sections.strings, &mut strings).unwrap();
+    (symbols, strings)
+}
+
+#[test]
+fn headers() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+
+    assert!(elf.is_position_independent());
+    assert_eq!(elf.entry_point(), ENTRY);
+
+    let headers: Vec<_> = elf.program_headers().collect::<Result<_>>().unwrap();
+    assert_eq!(headers.len(), PH_NUM);
+    assert!(headers[0].is_load() && headers[0].is_executable() && !headers[0].is_writable());
+    assert!(headers[1].is_load() && headers[1].is_writable() && !headers[1].is_executable());
+    assert!(!headers[2].is_load());
+
+    assert_eq!(elf.section_headers().count(), SH_NUM);
+}
+
+#[test]
+fn section_names_and_lookup() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+
+    let names = section_names(&mut elf).unwrap();
+    assert_eq!(names[0], "");
+    assert_eq!(names[1], ".text");
+    assert_eq!(names[SHSTRNDX], ".shstrtab");
+
+    let text = elf.find_section(".text").unwrap().unwrap();
+    assert_eq!((text.addr(), text.size()), (TEXT, 0x40));
+    assert!(elf.find_section(".missing").unwrap().is_none());
+    assert!(elf.find_section(".tex").unwrap().is_none());
+}
+
+#[test]
+fn read_sections() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+    let mut buffer = [0xFF; 0x40];
+
+    let text = elf.find_section(".text").unwrap().unwrap();
+    assert_eq!(elf.read_section(&text, &mut buffer), Ok(0x40));
+    assert_eq!(&buffer[..5], &[0x55, 0x89, 0xE5, 0x5D, 0xC3]);
+    assert_eq!(
+        elf.read_section(&text, &mut buffer[..0x3F]),
+        Err(Error::OutOfBounds)
+    );
+
+    let bss = elf.find_section(".bss").unwrap().unwrap();
+    assert_eq!(elf.read_section(&bss, &mut buffer), Ok(0));
+}
+
+#[test]
+fn symbols() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+
+    let sections = elf.symtab().unwrap().unwrap();
+    let (symbols, strings) = symbol_table(&mut elf, sections);
+    let table = SymbolTable::new(&symbols, &strings);
+    assert_eq!(table.symbols().count(), 4);
+
+    let name = |(symbol, offset): (Elf32Symbol, usize)| (table.name(&symbol), offset);
+    assert_eq!(table.lookup(ENTRY).map(name), Some((Some("entry"), 0)));
+    assert_eq!(table.lookup(ENTRY + 7).map(name), Some((Some("entry"), 7)));
+    // Without size, other goes up to the next symbol
+    assert_eq!(
+        table.lookup(ENTRY + 0x20).map(name),
+        Some((Some("other"), 0x18))
+    );
+    assert!(table.lookup(ENTRY - 1).is_none());
+
+    let object = table.symbols().find(|s| s.is_object()).unwrap();
+    assert_eq!(table.name(&object), Some("object"));
+    assert_eq!(object.section(), 6);
+
+    let sections = elf.dynsym().unwrap().unwrap();
+    let (symbols, strings) = symbol_table(&mut elf, sections);
+    let table = SymbolTable::new(&symbols, &strings);
+    let func = table.lookup(TEXT + 0x12).unwrap().0;
+    assert_eq!(table.name(&func), Some("func"));
+}
+
+#[test]
+fn notes() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+
+    let section = elf.find_section(".note.test").unwrap().unwrap();
+    assert!(section.is_note());
+    let mut buffer = vec![0; section.size()];
+    elf.read_section(&section, &mut buffer).unwrap();
+
+    let notes: Vec<_> = Notes::new(&buffer).collect();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].name, b"test");
+    assert_eq!(notes[0].typ, 1);
+    assert_eq!(notes[0].desc, b"abc");
+
+    // The descriptor goes past the end
+    assert_eq!(Notes::new(&buffer[..20]).count(), 0);
+}
+
+#[test]
+fn relocate() {
+    let image = executable();
+    let mut elf = open(&image.0).unwrap();
+    let mut memory = vec![0; MEMORY_SIZE];
+    load(&mut elf, &mut memory).unwrap();
+
+    let bias = 0x4000_0000;
+    elf.relocate(&mut memory, 0, bias).unwrap();
+    assert_eq!(
+        word(&memory, RELATIVE_WORD + DATA_BIAS),
+        bias as u32 + 0x100
+    );
+    assert_eq!(
+        word(&memory, ABSOLUTE_WORD + DATA_BIAS),
+        bias as u32 + 0x110 + 4
+    );
+}
+
+#[test]
+fn relocation_errors() {
+    let cases: &[Corruption] = &[
+        (|i| i.put32(REL + 4, 5), Error::UnknownRelocation(5)),
+        (|i| i.put16(DYNSYM + 16 + 14, 0), Error::UndefinedSymbol),
+        (|i| i.put32(REL, MEMORY_SIZE as u32 - 2), Error::OutOfBounds),
+        (|i| i.put32(REL + 12, 0xFFFF << 8 | 1), Error::OutOfBounds),
+        (|i| i.put32(DATA_OFF + 16, 7), Error::UnknownElf),
+    ];
+
+    for (corrupt, error) in cases {
+        let mut image = executable();
+        corrupt(&mut image);
+
+        let mut elf = open(&image.0).unwrap();
+        let mut memory = vec![0; MEMORY_SIZE];
+        load(&mut elf, &mut memory).unwrap();
+        assert_eq!(elf.relocate(&mut memory, 0, 0x1000), Err(*error));
+    }
+}
+
+#[test]
+fn invalid_headers() {
+    let cases: &[Corruption] = &[
+        (|i| i.put(0, b"\x7FELG"), Error::NotAnELF),
+        (|i| i.0[4] = 2, Error::UnknownElf),
+        (|i| i.0[5] = 2, Error::UnknownElf),
+        (|i| i.0[6] = 0, Error::UnknownElf),
+        (|i| i.put16(16, 9), Error::UnknownElf),
+        (|i| i.put16(42, 0), Error::UnknownElf),
+        (|i| i.put16(46, 64), Error::UnknownElf),
+        (|i| i.put16(44, 0xFFFF), Error::OutOfBounds),
+        (|i| i.put32(28, 0xFFFF_FFF0), Error::OutOfBounds),
+        (|i| i.put16(48, 0xFFFF), Error::OutOfBounds),
+        (|i| i.put32(32, 0xFFFF_FFFF), Error::OutOfBounds),
+        (|i| i.put16(50, SH_NUM as u16), Error::OutOfBounds),
+    ];
+
+    for (corrupt, error) in cases {
+        let mut image = executable();
+        corrupt(&mut image);
+        assert_eq!(open(&image.0).err(), Some(*error));
+    }
+
+    assert_eq!(open(&[]).err(), Some(Error::NotAnELF));
+}
+
+#[test]
+fn invalid_sections() {
+    let mut image = executable();
+    image.put32(SH_OFF + 40, SECTION_NAMES.len() as u32 + 1);
+    let mut elf = open(&image.0).unwrap();
+    assert_eq!(section_names(&mut elf).err(), Some(Error::OutOfBounds));
+
+    let mut image = executable();
+    image.put32(SH_OFF + 8 * 40 + 24, SH_NUM as u32);
+    let mut elf = open(&image.0).unwrap();
+    assert_eq!(elf.symtab().err(), Some(Error::OutOfBounds));
+
+    let mut image = executable();
+    image.put32(SH_OFF + 8 * 40 + 24, 1);
+    let mut elf = open(&image.0).unwrap();
+    assert_eq!(elf.symtab().err(), Some(Error::UnknownElf));
+
+    let mut image = executable();
+    image.put32(SH_OFF + 40 + 16, 0xFFFF_FF00);
+    let mut elf = open(&image.0).unwrap();
+    let text = elf.section(1).unwrap();
+    assert_eq!(elf.section_size(&text), Err(Error::OutOfBounds));
+    assert_eq!(
+        elf.read_section(&text, &mut [0; 0x40]),
+        Err(Error::OutOfBounds)
+    );
+
+    // A size larger than the file, rejected before anything is allocated
+    let mut image = executable();
+    image.put32(SH_OFF + 40 + 20, 0xFFFF_FF00);
+    let mut elf = open(&image.0).unwrap();
+    let text = elf.section(1).unwrap();
+    assert_eq!(elf.section_size(&text), Err(Error::OutOfBounds));
+
+    assert_eq!(elf.section(SH_NUM).err(), Some(Error::OutOfBounds));
+}
+
+/// Use every accessor, which must fail cleanly on malformed files
+fn exercise(data: &[u8]) {
+    let mut elf = match open(data) {
+        Ok(elf) => elf,
+        Err(_) => return,
+    };
+
+    let _ = elf.program_headers().count();
+    let _ = elf.section_headers().count();
+    let _ = section_names(&mut elf);
+    let _ = elf.find_section(".text");
+
+    // Allocate as the kernel does, with the sizes checked against the file
+    let read = |elf: &mut Elf<Cursor>, section: &Elf32SectionHeader| {
+        let mut buffer = vec![0; elf.section_size(section)?];
+        elf.read_section(section, &mut buffer)?;
+        Ok::<_, Error>(buffer)
+    };
+
+    for index in 0..SH_NUM {
+        if let Ok(section) = elf.section(index) {
+            if let Ok(buffer) = read(&mut elf, &section) {
+                assert!(buffer.len() <= data.len());
+                let _ = Notes::new(&buffer).count();
+            }
+        }
+    }
+
+    for sections in &[elf.symtab(), elf.dynsym()] {
+        if let Ok(Some(sections)) = sections {
+            let symbols = read(&mut elf, &sections.symbols);
+            let strings = read(&mut elf, &sections.strings);
+            if let (Ok(symbols), Ok(strings)) = (symbols, strings) {
+                let table = SymbolTable::new(&symbols, &strings);
+                for symbol in table.symbols() {
+                    let _ = table.name(&symbol);
+                }
+                let _ = table.lookup(ENTRY + 2);
+            }
+        }
+    }
+
+    let mut memory = vec![0; MEMORY_SIZE];
+    if load(&mut elf, &mut memory).is_ok() {
+        let _ = elf.relocate(&mut memory, 0, 0x1000);
+    }
+}
+
+#[test]
+fn truncated_files() {
+    let image = executable();
+    exercise(&image.0);
+
+    for size in 0..SIZE {
+        exercise(&image.0[..size]);
+    }
+}
+
+#[test]
+fn mutated_files() {
+    let image = executable();
+    // xorshift, so that the corpus is the same on every run
+    let mut state: u32 = 0x2545_F491;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize
+    };
+
+    for _ in 0..20_000 {
+        let mut data = image.0.clone();
+        for _ in 0..1 + random() % 4 {
+            let offset = random() % SIZE;
+            data[offset] = match random() % 3 {
+                0 => random() as u8,
+                1 => 0xFF,
+                _ => 0,
+            };
+        }
+        exercise(&data);
+    }
+}
@@ -0,0 +1,188 @@
+//! Local APIC of the CPU and I/O APIC, accessed through their memory mapped registers
+#![allow(dead_code)]
+
+use core::ptr::{read_volatile, write_volatile};
+
+use super::instructions::msr::{rdmsr, wrmsr};
+
+const MSR_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0xFFFF_F000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LocalApic {
+    base: u32,
+}
+
+impl LocalApic {
+    const REGISTER_ID: u32 = 0x20;
+    const REGISTER_TASK_PRIORITY: u32 = 0x80;
+    const REGISTER_EOI: u32 = 0xB0;
+    const REGISTER_SPURIOUS: u32 = 0xF0;
+    const REGISTER_LVT_TIMER: u32 = 0x320;
+    const REGISTER_LVT_LINT0: u32 = 0x350;
+    const REGISTER_LVT_LINT1: u32 = 0x360;
+    const REGISTER_TIMER_INITIAL_COUNT: u32 = 0x380;
+    const REGISTER_TIMER_CURRENT_COUNT: u32 = 0x390;
+    const REGISTER_TIMER_DIVIDE: u32 = 0x3E0;
+
+    const SPURIOUS_ENABLE: u32 = 1 << 8;
+    const LVT_MASKED: u32 = 1 << 16;
+    const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+    /// The timer counts down at the bus frequency divided by 16
+    const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+    /// Unsafe because the base must be the address of the local APIC registers
+    pub const unsafe fn new(base: u32) -> Self {
+        LocalApic { base }
+    }
+
+    /// Address of the registers, from the APIC base MSR.
+    /// Unsafe because the caller must check the CPU has an APIC.
+    pub unsafe fn base() -> u32 {
+        (rdmsr(MSR_APIC_BASE) & APIC_BASE_ADDRESS_MASK) as u32
+    }
+
+    /// Enable the APIC, and deliver its spurious interrupts to the vector.
+    /// Unsafe because the vector must have a handler which doesn't send an EOI.
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        wrmsr(MSR_APIC_BASE, rdmsr(MSR_APIC_BASE) | APIC_BASE_ENABLE);
+
+        // The legacy PIC is not wired through the local interrupt pins anymore
+        self.write(Self::REGISTER_LVT_LINT0, Self::LVT_MASKED);
+        self.write(Self::REGISTER_LVT_LINT1, Self::LVT_MASKED);
+        self.write(Self::REGISTER_LVT_TIMER, Self::LVT_MASKED);
+
+        // Accept every interrupt priority
+        self.write(Self::REGISTER_TASK_PRIORITY, 0);
+        self.write(Self::REGISTER_SPURIOUS, Self::SPURIOUS_ENABLE | spurious_vector as u32);
+    }
+
+    pub fn id(&self) -> u8 {
+        (unsafe { self.read(Self::REGISTER_ID) } >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(Self::REGISTER_EOI, 0) }
+    }
+
+    /// Start the timer counting down from `count`, without interrupt
+    pub fn start_timer_count(&self, count: u32) {
+        unsafe {
+            self.write(Self::REGISTER_TIMER_DIVIDE, Self::TIMER_DIVIDE_BY_16);
+            self.write(Self::REGISTER_LVT_TIMER, Self::LVT_MASKED);
+            self.write(Self::REGISTER_TIMER_INITIAL_COUNT, count);
+        }
+    }
+
+    pub fn timer_count(&self) -> u32 {
+        unsafe { self.read(Self::REGISTER_TIMER_CURRENT_COUNT) }
+    }
+
+    /// Raise the vector every `count` timer cycles
+    pub fn set_timer_periodic(&self, vector: u8, count: u32) {
+        unsafe {
+            self.write(Self::REGISTER_TIMER_DIVIDE, Self::TIMER_DIVIDE_BY_16);
+            self.write(Self::REGISTER_LVT_TIMER, Self::LVT_TIMER_PERIODIC | vector as u32);
+            self.write(Self::REGISTER_TIMER_INITIAL_COUNT, count);
+        }
+    }
+
+    pub fn stop_timer(&self) {
+        unsafe {
+            self.write(Self::REGISTER_LVT_TIMER, Self::LVT_MASKED);
+            self.write(Self::REGISTER_TIMER_INITIAL_COUNT, 0);
+        }
+    }
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        read_volatile((self.base + register) as *const u32)
+    }
+
+    unsafe fn write(&self, register: u32, value: u32) {
+        write_volatile((self.base + register) as *mut u32, value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IoApic {
+    base: u32,
+}
+
+impl IoApic {
+    const REGISTER_SELECT: u32 = 0x00;
+    const REGISTER_WINDOW: u32 = 0x10;
+
+    const VERSION: u8 = 0x01;
+    const REDIRECTION_TABLE: u8 = 0x10;
+
+    const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+    const REDIRECTION_LEVEL: u32 = 1 << 15;
+    const REDIRECTION_MASKED: u32 = 1 << 16;
+
+    /// Unsafe because the base must be the address of the I/O APIC registers
+    pub const unsafe fn new(base: u32) -> Self {
+        IoApic { base }
+    }
+
+    /// Number of interrupt inputs
+    pub fn inputs(&self) -> u8 {
+        (unsafe { self.read(Self::VERSION) } >> 16) as u8 + 1
+    }
+
+    /// Deliver the input to the vector of the local APIC, masked
+    pub fn set_redirection(
+        &self,
+        input: u8,
+        vector: u8,
+        polarity: Polarity,
+        trigger: Trigger,
+        destination: u8,
+    ) {
+        let mut low = Self::REDIRECTION_MASKED | vector as u32;
+        if polarity == Polarity::ActiveLow {
+            low |= Self::REDIRECTION_ACTIVE_LOW;
+        }
+        if trigger == Trigger::Level {
+            low |= Self::REDIRECTION_LEVEL;
+        }
+
+        let register = Self::REDIRECTION_TABLE + input * 2;
+        unsafe {
+            self.write(register, low);
+            self.write(register + 1, (destination as u32) << 24);
+        }
+    }
+
+    pub fn mask(&self, input: u8) {
+        let register = Self::REDIRECTION_TABLE + input * 2;
+        unsafe { self.write(register, self.read(register) | Self::REDIRECTION_MASKED) }
+    }
+
+    pub fn unmask(&self, input: u8) {
+        let register = Self::REDIRECTION_TABLE + input * 2;
+        unsafe { self.write(register, self.read(register) & !Self::REDIRECTION_MASKED) }
+    }
+
+    unsafe fn read(&self, register: u8) -> u32 {
+        write_volatile((self.base + Self::REGISTER_SELECT) as *mut u32, register as u32);
+        read_volatile((self.base + Self::REGISTER_WINDOW) as *const u32)
+    }
+
+    unsafe fn write(&self, register: u8, value: u32) {
+        write_volatile((self.base + Self::REGISTER_SELECT) as *mut u32, register as u32);
+        write_volatile((self.base + Self::REGISTER_WINDOW) as *mut u32, value)
+    }
+}
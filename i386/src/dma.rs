@@ -0,0 +1,111 @@
+//! ISA DMA controllers (8237): channels 0-3 transfer bytes, channels 4-7 transfer words
+#![allow(dead_code)]
+
+use super::instructions::Port;
+
+/// ISA DMA only reaches the first 16 MiB
+const MAX_ADDRESS: u32 = 0x100_0000;
+
+const MODE_READ: u8 = 0b10 << 2;
+const MODE_AUTO_INIT: u8 = 1 << 4;
+const MODE_SINGLE: u8 = 0b01 << 6;
+
+const MASK_ON: u8 = 1 << 2;
+
+struct Registers {
+    address: u16,
+    count: u16,
+    page: u16,
+    mask: u16,
+    mode: u16,
+    clear_flip_flop: u16,
+}
+
+fn registers(channel: u8) -> Option<Registers> {
+    let (address, count, page) = match channel {
+        0 => (0x00, 0x01, 0x87),
+        1 => (0x02, 0x03, 0x83),
+        2 => (0x04, 0x05, 0x81),
+        3 => (0x06, 0x07, 0x82),
+        5 => (0xC4, 0xC6, 0x8B),
+        6 => (0xC8, 0xCA, 0x89),
+        7 => (0xCC, 0xCE, 0x8A),
+        // Channel 4 cascades the first controller
+        _ => return None,
+    };
+
+    Some(if channel < 4 {
+        Registers {
+            address,
+            count,
+            page,
+            mask: 0x0A,
+            mode: 0x0B,
+            clear_flip_flop: 0x0C,
+        }
+    } else {
+        Registers {
+            address,
+            count,
+            page,
+            mask: 0xD4,
+            mode: 0xD6,
+            clear_flip_flop: 0xD8,
+        }
+    })
+}
+
+/// Program the channel to repeatedly send `length` bytes at the physical `address` to a device.
+///
+/// The buffer must be below 16 MiB and must not cross a 64 KiB boundary, or 128 KiB
+/// for 16 bits channels, whose address and length must also be even.
+pub unsafe fn setup_auto_init_read(channel: u8, address: u32, length: u32) -> Result<(), ()> {
+    let registers = registers(channel).ok_or(())?;
+    let wide = channel >= 4;
+    let boundary = if wide { 0x2_0000 } else { 0x1_0000 };
+
+    if length == 0
+        || address + length > MAX_ADDRESS
+        || address / boundary != (address + length - 1) / boundary
+        || wide && (address | length) & 1 != 0
+    {
+        return Err(());
+    }
+
+    // 16 bits channels count words, and their address is in words within a 128 KiB page
+    let (offset, count) = if wide {
+        ((address >> 1) & 0xFFFF, length / 2 - 1)
+    } else {
+        (address & 0xFFFF, length - 1)
+    };
+    if count > 0xFFFF {
+        return Err(());
+    }
+
+    let selector = channel & 3;
+    Port::<u8>::new(registers.mask).write(MASK_ON | selector);
+
+    Port::<u8>::new(registers.clear_flip_flop).write(0);
+    let mut address_port = Port::<u8>::new(registers.address);
+    address_port.write(offset as u8);
+    address_port.write((offset >> 8) as u8);
+    Port::<u8>::new(registers.page).write((address >> 16) as u8);
+
+    Port::<u8>::new(registers.clear_flip_flop).write(0);
+    let mut count_port = Port::<u8>::new(registers.count);
+    count_port.write(count as u8);
+    count_port.write((count >> 8) as u8);
+
+    Port::<u8>::new(registers.mode).write(MODE_SINGLE | MODE_AUTO_INIT | MODE_READ | selector);
+
+    Port::<u8>::new(registers.mask).write(selector);
+
+    Ok(())
+}
+
+/// Stop the transfers of the channel
+pub unsafe fn mask(channel: u8) {
+    if let Some(registers) = registers(channel) {
+        Port::<u8>::new(registers.mask).write(MASK_ON | (channel & 3));
+    }
+}
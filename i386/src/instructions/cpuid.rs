@@ -0,0 +1,78 @@
+const EFLAGS_ID: u32 = 1 << 21;
+
+const LEAF_FEATURES: u32 = 0x1;
+const LEAF_MAX_EXTENDED: u32 = 0x8000_0000;
+const LEAF_POWER_MANAGEMENT: u32 = 0x8000_0007;
+
+const FEATURES_EDX_TSC: u32 = 1 << 4;
+const FEATURES_EDX_MSR: u32 = 1 << 5;
+const FEATURES_EDX_APIC: u32 = 1 << 9;
+const POWER_MANAGEMENT_EDX_INVARIANT_TSC: u32 = 1 << 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Returns true if the ID flag of EFLAGS can be toggled, which means CPUID is supported
+pub fn has_cpuid() -> bool {
+    let original: u32;
+    let toggled: u32;
+
+    unsafe {
+        llvm_asm!("pushfl
+        popl $0
+        movl $0, $1
+        xorl $2, $1
+        pushl $1
+        popfl
+        pushfl
+        popl $1
+        pushl $0
+        popfl"
+             : "=&r" (original), "=&r" (toggled)
+             : "i" (EFLAGS_ID)
+             : "cc"
+             : "volatile");
+    }
+
+    (original ^ toggled) & EFLAGS_ID != 0
+}
+
+/// Unsafe because the caller must check CPUID is supported
+pub unsafe fn cpuid(leaf: u32) -> CpuidResult {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+
+    llvm_asm!("cpuid"
+         : "={eax}" (eax), "={ebx}" (ebx), "={ecx}" (ecx), "={edx}" (edx)
+         : "{eax}" (leaf), "{ecx}" (0)
+         :
+         : "volatile");
+
+    CpuidResult { eax, ebx, ecx, edx }
+}
+
+/// Returns true if the CPU has a time stamp counter
+pub fn has_tsc() -> bool {
+    has_cpuid() && unsafe { cpuid(LEAF_FEATURES) }.edx & FEATURES_EDX_TSC != 0
+}
+
+/// Returns true if the CPU has a local APIC, and the MSRs to configure it
+pub fn has_apic() -> bool {
+    has_cpuid()
+        && unsafe { cpuid(LEAF_FEATURES) }.edx & (FEATURES_EDX_MSR | FEATURES_EDX_APIC)
+            == FEATURES_EDX_MSR | FEATURES_EDX_APIC
+}
+
+/// Returns true if the time stamp counter runs at a constant rate in every power state
+pub fn has_invariant_tsc() -> bool {
+    has_tsc()
+        && unsafe { cpuid(LEAF_MAX_EXTENDED) }.eax >= LEAF_POWER_MANAGEMENT
+        && unsafe { cpuid(LEAF_POWER_MANAGEMENT) }.edx & POWER_MANAGEMENT_EDX_INVARIANT_TSC != 0
+}
@@ -1,5 +1,8 @@
+pub mod cpuid;
 pub mod idt;
 pub mod lgdt;
+pub mod msr;
+pub mod tsc;
 
 mod port;
 
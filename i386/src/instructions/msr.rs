@@ -0,0 +1,24 @@
+/// Read a model specific register.
+/// Unsafe because the caller must check the register exists.
+pub unsafe fn rdmsr(register: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    llvm_asm!("rdmsr"
+         : "={eax}" (low), "={edx}" (high)
+         : "{ecx}" (register)
+         :
+         : "volatile");
+
+    (high as u64) << 32 | low as u64
+}
+
+/// Write a model specific register.
+/// Unsafe because the caller must check the register exists.
+pub unsafe fn wrmsr(register: u32, value: u64) {
+    llvm_asm!("wrmsr"
+         :
+         : "{ecx}" (register), "{eax}" (value as u32), "{edx}" ((value >> 32) as u32)
+         :
+         : "volatile");
+}
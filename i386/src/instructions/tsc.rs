@@ -0,0 +1,15 @@
+/// Read the time stamp counter.
+/// Unsafe because the caller must check the CPU has one.
+#[inline]
+pub unsafe fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+
+    llvm_asm!("rdtsc"
+         : "={eax}" (low), "={edx}" (high)
+         :
+         :
+         : "volatile");
+
+    (high as u64) << 32 | low as u64
+}
@@ -1,6 +1,8 @@
 #![feature(llvm_asm)]
 #![cfg_attr(feature = "no_std", no_std)]
 
+pub mod apic;
+pub mod dma;
 pub mod instructions;
 pub mod pic;
 pub mod pit;
@@ -26,6 +26,17 @@ impl Pic {
         }
     }
 
+    /// Interrupt number of the IRQ 0
+    pub const MASTER_OFFSET: u8 = 0x40;
+    /// Interrupt number of the IRQ 8
+    pub const SLAVE_OFFSET: u8 = 0x50;
+    pub const IRQ_COUNT: u8 = 16;
+    /// Line of the master connected to the slave
+    pub const CASCADE_IRQ: u8 = 2;
+
+    const END_OF_INTERRUPT: u8 = 0x20;
+    const READ_IN_SERVICE: u8 = 0x0B;
+
     pub fn init(&mut self) {
         unsafe {
             // ICW1
@@ -33,28 +44,116 @@ impl Pic {
             self.slave_a.write(0x11);
 
             // ICW2
-            self.master_b.write(0x40);
-            self.slave_b.write(0x50);
+            self.master_b.write(Self::MASTER_OFFSET);
+            self.slave_b.write(Self::SLAVE_OFFSET);
 
             // ICW3
-            self.master_b.write(0b10);
-            self.slave_b.write(0x2); // FIXME Or 0x1 ?
+            self.master_b.write(1 << Self::CASCADE_IRQ);
+            self.slave_b.write(Self::CASCADE_IRQ);
 
             // ICW4
             self.master_b.write(1);
             self.slave_b.write(1);
 
-            // Mask all interrupts except keyboard and PIT
-            self.master_b.write(0b11111100);
+            // Mask all interrupts except the cascade, until handlers are registered
+            self.master_b.write(!(1 << Self::CASCADE_IRQ));
+            self.slave_b.write(0xFF);
+        }
+    }
+
+    /// Mask every line, when interrupts are delivered by the APIC instead
+    pub fn mask_all(&mut self) {
+        unsafe {
+            self.master_b.write(0xFF);
+            self.slave_b.write(0xFF);
+        }
+    }
+
+    /// Interrupt number of the IRQ
+    pub fn interrupt(irq: u8) -> u8 {
+        if irq < 8 {
+            Self::MASTER_OFFSET + irq
+        } else {
+            Self::SLAVE_OFFSET + irq - 8
+        }
+    }
+
+    /// IRQ raising the interrupt, if any
+    pub fn irq(interrupt: u8) -> Option<u8> {
+        match interrupt {
+            i if i >= Self::MASTER_OFFSET && i < Self::MASTER_OFFSET + 8 => {
+                Some(i - Self::MASTER_OFFSET)
+            }
+            i if i >= Self::SLAVE_OFFSET && i < Self::SLAVE_OFFSET + 8 => {
+                Some(i - Self::SLAVE_OFFSET + 8)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn unmask(&mut self, irq: u8) {
+        let (port, line) = self.data_port(irq);
+        unsafe {
+            let mask = port.read();
+            port.write(mask & !(1 << line));
+        }
+    }
+
+    pub fn mask(&mut self, irq: u8) {
+        let (port, line) = self.data_port(irq);
+        unsafe {
+            let mask = port.read();
+            port.write(mask | 1 << line);
+        }
+    }
+
+    fn data_port(&mut self, irq: u8) -> (&mut Port<u8>, u8) {
+        if irq < 8 {
+            (&mut self.master_b, irq)
+        } else {
+            (&mut self.slave_b, irq - 8)
+        }
+    }
+
+    /// Returns true if the IRQ 7 or 15 was raised by noise and not by a device.
+    /// Spurious IRQs must not be acknowledged, but the master still expects an EOI
+    /// for the cascade when the slave raised one.
+    pub fn is_spurious(&mut self, irq: u8) -> bool {
+        let (command, line) = match irq {
+            7 => (&mut self.master_a, 7),
+            15 => (&mut self.slave_a, 7),
+            _ => return false,
+        };
+
+        let in_service = unsafe {
+            command.write(Self::READ_IN_SERVICE);
+            command.read()
+        };
+        if in_service & 1 << line != 0 {
+            return false;
+        }
+
+        if irq == 15 {
+            self.send_eoi_to_master();
+        }
+        true
+    }
+
+    /// Acknowledge the IRQ on the PICs it went through
+    pub fn end_of_interrupt(&mut self, irq: u8) {
+        if irq < 8 {
+            self.send_eoi_to_master();
+        } else {
+            self.send_eoi();
         }
     }
 
     pub fn send_eoi_to_master(&mut self) {
-        unsafe { self.master_a.write(0x20) }
+        unsafe { self.master_a.write(Self::END_OF_INTERRUPT) }
     }
 
     pub fn send_eoi(&mut self) {
+        unsafe { self.slave_a.write(Self::END_OF_INTERRUPT) }
         self.send_eoi_to_master();
-        unsafe { self.slave_a.write(0x20) }
     }
 }
@@ -11,13 +11,16 @@ pub struct Pit {
     control: Port<u8>,
     counter_0: Port<u8>,
     counter_2: Port<u8>,
+    /// Gate of the counter 2 and speaker control
+    speaker: Port<u8>,
 }
 
 impl Pit {
-    const FREQUENCY: u32 = 1193182;
+    pub const FREQUENCY: u32 = 1193182;
 
     const BINARY_COUNTER: u8 = 0;
 
+    const INTERRUPT_ON_TERMINAL_COUNT_MODE: u8 = 0 << 1;
     const RATE_GENERATOR_MODE: u8 = 2 << 1;
     const SQUARE_MODE: u8 = 3 << 1;
 
@@ -27,11 +30,25 @@ impl Pit {
 
     const SETUP_COUNTER_2: u8 = 2 << 6;
 
+    const READ_BACK: u8 = 3 << 6;
+    /// Read-back flags are active low
+    const READ_BACK_COUNT_AND_STATUS: u8 = 0;
+    const READ_BACK_COUNTER_0: u8 = 1 << 1;
+
+    const STATUS_OUTPUT: u8 = 1 << 7;
+    /// The count written last is not loaded in the counter yet
+    const STATUS_NULL_COUNT: u8 = 1 << 6;
+
+    const SPEAKER_GATE: u8 = 1 << 0;
+    const SPEAKER_DATA: u8 = 1 << 1;
+    const SPEAKER_COUNTER_2_OUTPUT: u8 = 1 << 5;
+
     fn new_8254() -> Self {
         Pit {
             control: Port::new(0x43),
             counter_0: Port::new(0x40),
             counter_2: Port::new(0x42),
+            speaker: Port::new(0x61),
         }
     }
 
@@ -50,6 +67,64 @@ impl Pit {
         }
     }
 
+    /// Raise a single interrupt after `cycles` periods of the input clock
+    pub fn set_one_shot(&mut self, cycles: u16) {
+        unsafe {
+            self.control.write(
+                Pit::BINARY_COUNTER
+                    | Pit::INTERRUPT_ON_TERMINAL_COUNT_MODE
+                    | Pit::POLICY_LSB_MSB
+                    | Pit::SETUP_COUNTER_0,
+            );
+
+            self.counter_0.write((cycles & 0xFF) as u8);
+            self.counter_0.write(((cycles >> 8) & 0xFF) as u8);
+        }
+    }
+
+    /// Returns the remaining count of the counter 0, or None if the terminal count was reached
+    /// or if the count read back is still the previous one
+    pub fn read_one_shot(&mut self) -> Option<u16> {
+        unsafe {
+            self.control.write(
+                Pit::READ_BACK | Pit::READ_BACK_COUNT_AND_STATUS | Pit::READ_BACK_COUNTER_0,
+            );
+
+            let status = self.counter_0.read();
+            let count = self.counter_0.read() as u16 | (self.counter_0.read() as u16) << 8;
+
+            if status & (Pit::STATUS_OUTPUT | Pit::STATUS_NULL_COUNT) != 0 {
+                None
+            } else {
+                Some(count)
+            }
+        }
+    }
+
+    /// Busy wait `cycles` periods of the input clock, with the counter 2 and the speaker muted.
+    /// Usable before interrupts are enabled, but it stops the sound.
+    pub fn wait(&mut self, cycles: u16) {
+        unsafe {
+            let gate = self.speaker.read() & !(Pit::SPEAKER_GATE | Pit::SPEAKER_DATA);
+            self.speaker.write(gate);
+
+            self.control.write(
+                Pit::BINARY_COUNTER
+                    | Pit::INTERRUPT_ON_TERMINAL_COUNT_MODE
+                    | Pit::POLICY_LSB_MSB
+                    | Pit::SETUP_COUNTER_2,
+            );
+            self.counter_2.write(cycles as u8);
+            self.counter_2.write((cycles >> 8) as u8);
+
+            // The counter starts on the rising edge of the gate
+            self.speaker.write(gate | Pit::SPEAKER_GATE);
+            while self.speaker.read() & Pit::SPEAKER_COUNTER_2_OUTPUT == 0 {}
+
+            self.speaker.write(gate);
+        }
+    }
+
     pub fn play_sound(&mut self, frequency: u32) {
         let div: u16 = (Self::FREQUENCY / frequency) as u16;
 
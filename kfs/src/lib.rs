@@ -0,0 +1,420 @@
+//! KFS, the read-only file system of the ROMs built by `tools/mkkfs`
+//!
+//! The image is a sequence of blocks: the superblock, then the inodes and the data blocks.
+//! Every block has a checksum, which is verified when the image is opened.
+
+#![cfg_attr(feature = "no_std", no_std)]
+
+extern crate no_std_io;
+
+mod reader;
+#[cfg(test)]
+mod tests;
+
+use core::mem::{align_of, size_of, size_of_val};
+use core::slice;
+use core::str;
+
+use no_std_io::{Read, Seek};
+
+use self::reader::DataBlockReader;
+
+const MAGIC: u32 = 0xd35f9caa;
+const NAME_SIZE: usize = 32;
+const FNAME_SIZE: usize = 32;
+const BLK_SIZE: usize = 4096;
+const BLK_DATA_SIZE: usize = BLK_SIZE - 3 * 4;
+const MAX_DIRECT_BLK: usize = 10;
+const MAX_INDIRECT_BLK: usize = 16;
+const INDIRECT_BLK_CNT: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidChecksum,
+    InvalidMagic,
+    InvalidName,
+    MemTooSmall,
+    Misaligned,
+    OutOfBounds,
+}
+
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+pub trait FileHandle: Read + Seek {}
+
+/// File system in memory, whose blocks were all validated
+#[derive(Clone, Copy, Debug)]
+pub struct Kfs<'a> {
+    blocks: &'a [Block],
+}
+
+impl<'a> Kfs<'a> {
+    /// Validate the image, which must hold all the blocks, and be aligned like them
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if data.len() < size_of::<Superblock>() {
+            return Err(Error::MemTooSmall);
+        }
+        if data.as_ptr().align_offset(align_of::<Block>()) != 0 {
+            return Err(Error::Misaligned);
+        }
+
+        let superblock = unsafe { &*(data.as_ptr() as *const Superblock) };
+        superblock.validate()?;
+
+        let blk_cnt = superblock.blk_cnt as usize;
+        if data.len() / BLK_SIZE < blk_cnt {
+            return Err(Error::MemTooSmall);
+        }
+
+        let kfs = Kfs {
+            blocks: unsafe { slice::from_raw_parts(data.as_ptr() as *const Block, blk_cnt) },
+        };
+        kfs.validate()?;
+
+        Ok(kfs)
+    }
+
+    fn superblock(&self) -> &'a Superblock {
+        unsafe { self.blocks[0].as_superblock() }
+    }
+
+    pub fn name(&self) -> &'a str {
+        cstr(&self.superblock().name).unwrap_or("")
+    }
+
+    /// Creation time, in seconds since the Unix epoch
+    pub fn ctime(&self) -> i64 {
+        self.superblock().ctime as i64
+    }
+
+    pub fn inodes(&self) -> impl Iterator<Item = &'a Inode> {
+        InodeIterator::new(*self)
+    }
+
+    /// Find a file by name, with or without a leading slash
+    pub fn find(&self, path: &str) -> Option<&'a Inode> {
+        let filename = path.strip_prefix('/').unwrap_or(path);
+        self.inodes().find(|i| i.filename() == filename)
+    }
+
+    pub fn reader(&self, inode: &'a Inode) -> impl FileHandle + Clone + 'a {
+        DataBlockReader::new(inode.blocks(*self))
+    }
+
+    fn block(&self, index: u32) -> Result<&'a Block> {
+        self.blocks.get(index as usize).ok_or(Error::OutOfBounds)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let mut inodes = InodeIterator::new(*self);
+        for inode in &mut inodes {
+            inode.validate(*self)?;
+        }
+
+        // The chain ends with a null index, unless it loops or goes out of the image
+        if inodes.idx != 0 {
+            return Err(Error::OutOfBounds);
+        }
+
+        Ok(())
+    }
+}
+
+// Could use union, but non-copy union are unstable
+#[repr(C, align(4))]
+struct Block([u8; BLK_SIZE]);
+
+impl Block {
+    unsafe fn as_superblock(&self) -> &Superblock {
+        &*(self as *const Block as *const Superblock)
+    }
+
+    unsafe fn as_inode(&self) -> &Inode {
+        &*(self as *const Block as *const Inode)
+    }
+
+    unsafe fn as_indirect(&self) -> &IndirectBlock {
+        &*(self as *const Block as *const IndirectBlock)
+    }
+
+    unsafe fn as_data(&self) -> &DataBlock {
+        &*(self as *const Block as *const DataBlock)
+    }
+}
+
+impl ::core::fmt::Debug for Block {
+    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+        f.write_str("Block")
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+struct Superblock {
+    magic: u32,
+    name: [u8; NAME_SIZE],
+    ctime: i32,
+    blk_cnt: u32,
+    inode_cnt: u32,
+    inode_idx: u32,
+    checksum: u32,
+}
+
+impl Superblock {
+    fn validate(&self) -> Result<()> {
+        if self.magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let self_begin = unsafe {
+            slice::from_raw_parts(
+                self as *const Superblock as *const u8,
+                size_of::<Superblock>() - size_of_val(&self.checksum),
+            )
+        };
+
+        if self.checksum != adler_checksum(self_begin) {
+            return Err(Error::InvalidChecksum);
+        }
+
+        if self.blk_cnt == 0 {
+            return Err(Error::OutOfBounds);
+        }
+
+        cstr(&self.name).map(|_| ())
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct Inode {
+    number: i32,
+    filename: [u8; FNAME_SIZE],
+    size: u32,
+    idx: u32,
+    blk_count: u32,
+    next_inode: u32,
+    d_blk_cnt: u32,
+    i_blk_cnt: u32,
+    d_blks: [u32; MAX_DIRECT_BLK],
+    i_blks: [u32; MAX_INDIRECT_BLK],
+    checksum: u32,
+}
+
+impl Inode {
+    fn validate(&self, kfs: Kfs) -> Result<()> {
+        // Validate checksum
+        let self_begin = unsafe {
+            slice::from_raw_parts(
+                self as *const Inode as *const u8,
+                size_of::<Inode>() - size_of_val(&self.checksum),
+            )
+        };
+
+        if adler_checksum(self_begin) != self.checksum {
+            return Err(Error::InvalidChecksum);
+        }
+
+        // Check if next inode and number of blk count are not out of bounds
+        if self.d_blk_cnt as usize > MAX_DIRECT_BLK
+            || self.i_blk_cnt as usize > MAX_INDIRECT_BLK
+            || self.next_inode as usize >= kfs.blocks.len()
+        {
+            return Err(Error::OutOfBounds);
+        }
+
+        cstr(&self.filename)?;
+
+        // Check if direct and indirects blocks are out of bounds
+        for &index in self
+            .direct_blocks_idx()
+            .iter()
+            .chain(self.indirect_blocks_idx())
+        {
+            kfs.block(index)?;
+        }
+
+        for &index in self.indirect_blocks_idx() {
+            unsafe { kfs.block(index)?.as_indirect() }.validate(kfs)?;
+        }
+
+        self.blocks(kfs).try_for_each(DataBlock::validate)
+    }
+
+    fn direct_blocks_idx(&self) -> &[u32] {
+        &self.d_blks[..self.d_blk_cnt as usize]
+    }
+
+    fn indirect_blocks_idx(&self) -> &[u32] {
+        &self.i_blks[..self.i_blk_cnt as usize]
+    }
+
+    /// The blocks must have been validated
+    fn blocks<'a>(&'a self, kfs: Kfs<'a>) -> impl Iterator<Item = &'a DataBlock> + Clone {
+        let indirect = self
+            .indirect_blocks_idx()
+            .iter()
+            .flat_map(move |&index| unsafe { kfs.blocks[index as usize].as_indirect() }.ids());
+
+        self.direct_blocks_idx()
+            .iter()
+            .chain(indirect)
+            .map(move |&index| unsafe { kfs.blocks[index as usize].as_data() })
+    }
+
+    pub fn filename(&self) -> &str {
+        cstr(&self.filename).unwrap_or("")
+    }
+
+    /// Size of the file, in bytes
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+}
+
+struct InodeIterator<'a> {
+    kfs: Kfs<'a>,
+    idx: u32,
+    /// Inodes left before the chain is considered as looping
+    remaining: usize,
+}
+
+impl<'a> InodeIterator<'a> {
+    fn new(kfs: Kfs<'a>) -> Self {
+        InodeIterator {
+            kfs,
+            idx: kfs.superblock().inode_idx,
+            remaining: kfs.blocks.len(),
+        }
+    }
+}
+
+impl<'a> Iterator for InodeIterator<'a> {
+    type Item = &'a Inode;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.idx == 0 || self.remaining == 0 {
+            return None;
+        }
+
+        let inode = unsafe { self.kfs.block(self.idx).ok()?.as_inode() };
+        self.idx = inode.next_inode;
+        self.remaining -= 1;
+        Some(inode)
+    }
+}
+
+/// Block listing data blocks, for the files too large for the direct ones
+#[repr(C)]
+struct IndirectBlock {
+    index: u32,
+    blk_cnt: u32,
+    blks: [u32; INDIRECT_BLK_CNT],
+    checksum: u32,
+}
+
+impl IndirectBlock {
+    fn validate(&self, kfs: Kfs) -> Result<()> {
+        let self_begin = unsafe {
+            slice::from_raw_parts(
+                self as *const IndirectBlock as *const u8,
+                size_of::<IndirectBlock>() - size_of_val(&self.checksum),
+            )
+        };
+
+        if adler_checksum(self_begin) != self.checksum {
+            return Err(Error::InvalidChecksum);
+        }
+
+        if self.blk_cnt as usize > INDIRECT_BLK_CNT {
+            return Err(Error::OutOfBounds);
+        }
+
+        for &index in self.ids() {
+            kfs.block(index)?;
+        }
+
+        Ok(())
+    }
+
+    fn ids(&self) -> slice::Iter<'_, u32> {
+        self.blks[..self.blk_cnt as usize].iter()
+    }
+}
+
+#[repr(C)]
+struct DataBlock {
+    index: u32,
+    usage: u32,
+    checksum: u32,
+    data: [u8; BLK_DATA_SIZE],
+}
+
+impl DataBlock {
+    fn usage(&self) -> usize {
+        self.usage as usize
+    }
+
+    fn read(&self, buffer: &mut [u8], initial_cursor: usize) -> usize {
+        use core::cmp::min;
+
+        if initial_cursor >= self.usage() {
+            return 0;
+        }
+
+        let to_copy = min(self.usage() - initial_cursor, buffer.len());
+        buffer[..to_copy].copy_from_slice(&self.data[initial_cursor..initial_cursor + to_copy]);
+        to_copy
+    }
+
+    fn validate(&self) -> Result<()> {
+        let self_begin = unsafe {
+            slice::from_raw_parts(
+                self as *const DataBlock as *const u8,
+                size_of_val(&self.index) + size_of_val(&self.usage),
+            )
+        };
+
+        // Introduce false checksum because the algorithm check on all the data,
+        // and expect the checksum to be equal to 0.
+        let false_checksum = [0; size_of::<u32>()];
+        let checksum = adler_checksum(
+            self_begin
+                .iter()
+                .chain(false_checksum.iter())
+                .chain(self.data.iter()),
+        );
+        if checksum != self.checksum {
+            return Err(Error::InvalidChecksum);
+        }
+
+        if self.usage() > BLK_DATA_SIZE {
+            return Err(Error::OutOfBounds);
+        }
+
+        Ok(())
+    }
+}
+
+/// Null terminated string, or the whole array without terminator
+fn cstr(bytes: &[u8]) -> Result<&str> {
+    let length = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    str::from_utf8(&bytes[..length]).map_err(|_| Error::InvalidName)
+}
+
+fn adler_checksum<'a, I>(data: I) -> u32
+where
+    I: IntoIterator<Item = &'a u8>,
+{
+    const ALDER32_MOD: u32 = 65521;
+
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    for &c in data {
+        a = (a + c as u32) % ALDER32_MOD;
+        b = (a + b) % ALDER32_MOD;
+    }
+
+    b << 16 | a
+}
@@ -26,7 +26,7 @@ where
     /// Go to the next block
     fn next_block(&mut self) {
         if let Some(block) = self.current {
-            self.offset += block.usage - self.current_offset;
+            self.offset += block.usage() - self.current_offset;
             self.current_offset = 0;
             self.current = self.iter.next();
         }
@@ -47,7 +47,7 @@ where
         while self.offset != offset {
             if let Some(block) = self.current {
                 let remaining_offset = offset - self.offset;
-                if (block.usage - self.current_offset) >= remaining_offset {
+                if (block.usage() - self.current_offset) >= remaining_offset {
                     self.current_offset += remaining_offset;
                     self.offset += remaining_offset;
                 } else {
@@ -120,16 +120,28 @@ where
     fn seek(&mut self, from: SeekFrom) -> Result<usize> {
         match from {
             SeekFrom::Start(offset) => self.seek_at(offset),
-            SeekFrom::Current(offset) => self.seek_at((self.offset as isize + offset) as usize),
+            SeekFrom::Current(offset) => self.seek_at(add_offset(self.offset, offset)?),
             SeekFrom::End(offset) => {
                 self.go_to_end();
-                self.seek_at((self.offset as isize + offset) as usize)
+                self.seek_at(add_offset(self.offset, offset)?)
             }
         }
         .map(|()| self.offset)
     }
 }
 
-impl<'a, I> super::FileHandle for DataBlockReader<'a, I>
-    where
-        I: Iterator<Item = &'a DataBlock> + Clone {}
+impl<'a, I> super::FileHandle for DataBlockReader<'a, I> where
+    I: Iterator<Item = &'a DataBlock> + Clone
+{
+}
+
+/// Seeking before the start of the file is an error
+fn add_offset(position: usize, offset: isize) -> Result<usize> {
+    if offset < 0 {
+        position
+            .checked_sub(offset.wrapping_neg() as usize)
+            .ok_or(())
+    } else {
+        position.checked_add(offset as usize).ok_or(())
+    }
+}
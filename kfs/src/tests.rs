@@ -0,0 +1,387 @@
+//! Images are built in memory like `tools/mkkfs` does: the superblock, the inodes, then the
+//! data blocks of each file, the indirect blocks following the data blocks they list.
+
+use std::slice;
+
+use no_std_io::SeekFrom;
+
+use super::*;
+
+const SUPERBLOCK_CHECKSUM: usize = 52;
+const INODE_NEXT: usize = 48;
+const INODE_D_BLKS: usize = 60;
+const INODE_CHECKSUM: usize = 164;
+const DATA_USAGE: usize = 4;
+const DATA_CHECKSUM: usize = 8;
+const INDIRECT_CHECKSUM: usize = 72;
+
+/// Image stored in words, to be aligned like the blocks
+struct Image {
+    words: Vec<u32>,
+}
+
+impl Image {
+    fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+        let mut blocks: Vec<Vec<u8>> = vec![vec![0; BLK_SIZE]; 1 + files.len()];
+
+        for (i, (filename, content)) in files.iter().enumerate() {
+            let inode_idx = 1 + i;
+            let mut chunks = content.chunks(BLK_DATA_SIZE);
+            let mut inode = vec![0; BLK_SIZE];
+
+            let mut d_blk_cnt = 0;
+            for chunk in chunks.by_ref().take(MAX_DIRECT_BLK) {
+                put(
+                    &mut inode,
+                    INODE_D_BLKS + 4 * d_blk_cnt,
+                    blocks.len() as u32,
+                );
+                blocks.push(data_block(blocks.len(), chunk));
+                d_blk_cnt += 1;
+            }
+
+            let mut i_blk_cnt = 0;
+            while chunks.len() != 0 {
+                let mut indirect = vec![0; BLK_SIZE];
+                let mut blk_cnt = 0;
+                for chunk in chunks.by_ref().take(INDIRECT_BLK_CNT) {
+                    put(&mut indirect, 8 + 4 * blk_cnt, blocks.len() as u32);
+                    blocks.push(data_block(blocks.len(), chunk));
+                    blk_cnt += 1;
+                }
+
+                put(&mut indirect, 0, blocks.len() as u32);
+                put(&mut indirect, 4, blk_cnt as u32);
+                seal(&mut indirect, INDIRECT_CHECKSUM);
+                let offset = INODE_D_BLKS + 4 * MAX_DIRECT_BLK + 4 * i_blk_cnt;
+                put(&mut inode, offset, blocks.len() as u32);
+                blocks.push(indirect);
+                i_blk_cnt += 1;
+            }
+
+            let next_inode = if i + 1 == files.len() {
+                0
+            } else {
+                inode_idx + 1
+            };
+            put(&mut inode, 0, inode_idx as u32);
+            inode[4..4 + filename.len()].copy_from_slice(filename.as_bytes());
+            put(&mut inode, 36, content.len() as u32);
+            put(&mut inode, 40, inode_idx as u32);
+            put(&mut inode, 44, (d_blk_cnt + i_blk_cnt) as u32);
+            put(&mut inode, INODE_NEXT, next_inode as u32);
+            put(&mut inode, 52, d_blk_cnt as u32);
+            put(&mut inode, 56, i_blk_cnt as u32);
+            seal(&mut inode, INODE_CHECKSUM);
+            blocks[inode_idx] = inode;
+        }
+
+        let superblock = &mut blocks[0];
+        put(superblock, 0, MAGIC);
+        superblock[4..4 + name.len()].copy_from_slice(name.as_bytes());
+        put(superblock, 36, 1_600_000_000);
+        let blk_cnt = blocks.len() as u32;
+        put(&mut blocks[0], 40, blk_cnt);
+        put(&mut blocks[0], 44, files.len() as u32);
+        put(&mut blocks[0], 48, if files.is_empty() { 0 } else { 1 });
+        seal(&mut blocks[0], SUPERBLOCK_CHECKSUM);
+
+        let mut image = Image {
+            words: vec![0; blocks.len() * BLK_SIZE / 4],
+        };
+        image.bytes_mut().copy_from_slice(&blocks.concat());
+        image
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.words.len() * 4) }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.words.len() * 4)
+        }
+    }
+
+    fn block_mut(&mut self, index: usize) -> &mut [u8] {
+        &mut self.bytes_mut()[index * BLK_SIZE..(index + 1) * BLK_SIZE]
+    }
+
+    fn open(&self) -> Result<Kfs<'_>> {
+        Kfs::new(self.bytes())
+    }
+}
+
+fn data_block(index: usize, chunk: &[u8]) -> Vec<u8> {
+    let mut block = vec![0; BLK_SIZE];
+    put(&mut block, 0, index as u32);
+    put(&mut block, DATA_USAGE, chunk.len() as u32);
+    block[12..12 + chunk.len()].copy_from_slice(chunk);
+    seal_data(&mut block);
+    block
+}
+
+fn put(block: &mut [u8], offset: usize, value: u32) {
+    block[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Checksum of the bytes before it
+fn seal(block: &mut [u8], checksum: usize) {
+    let value = adler_checksum(&block[..checksum]);
+    put(block, checksum, value);
+}
+
+/// Checksum of the whole block, with a null checksum
+fn seal_data(block: &mut [u8]) {
+    put(block, DATA_CHECKSUM, 0);
+    let value = adler_checksum(block.iter());
+    put(block, DATA_CHECKSUM, value);
+}
+
+/// Bytes which differ from a block to the next one
+fn content(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+fn read_all<R: Read>(reader: &mut R, chunk_size: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut chunk = vec![0; chunk_size];
+    loop {
+        let read = reader.read(&mut chunk).unwrap();
+        if read == 0 {
+            return data;
+        }
+        data.extend_from_slice(&chunk[..read]);
+    }
+}
+
+#[test]
+fn test_open() {
+    let image = Image::new("test fs", &[("a.txt", b"first"), ("b.txt", b"second")]);
+    let kfs = image.open().expect("Should open");
+
+    assert_eq!(kfs.name(), "test fs");
+    assert_eq!(kfs.ctime(), 1_600_000_000);
+    let names: Vec<&str> = kfs.inodes().map(Inode::filename).collect();
+    assert_eq!(names, ["a.txt", "b.txt"]);
+
+    assert_eq!(kfs.find("/b.txt").map(Inode::size), Some(6));
+    assert_eq!(kfs.find("b.txt").map(Inode::size), Some(6));
+    assert!(kfs.find("c.txt").is_none());
+}
+
+#[test]
+fn test_empty() {
+    let image = Image::new("empty", &[]);
+    let kfs = image.open().expect("Should open");
+    assert_eq!(kfs.inodes().count(), 0);
+
+    let image = Image::new("empty file", &[("empty", b"")]);
+    let kfs = image.open().expect("Should open");
+    let mut reader = kfs.reader(kfs.find("empty").unwrap());
+    assert_eq!(reader.read(&mut [0; 16]), Ok(0));
+    assert_eq!(reader.seek(SeekFrom::End(0)), Ok(0));
+}
+
+#[test]
+fn test_read_small() {
+    let image = Image::new("fs", &[("text", b"This is the text!")]);
+    let kfs = image.open().unwrap();
+    let mut reader = kfs.reader(kfs.find("text").unwrap());
+
+    let mut buffer = [0; 64];
+    assert_eq!(reader.read(&mut buffer), Ok(17));
+    assert_eq!(&buffer[..17], b"This is the text!");
+    assert_eq!(reader.read(&mut buffer), Ok(0));
+}
+
+#[test]
+fn test_read_multiple_blocks() {
+    let data = content(3 * BLK_DATA_SIZE + 100);
+    let image = Image::new("fs", &[("file", &data)]);
+    let kfs = image.open().unwrap();
+    let reader = kfs.reader(kfs.find("file").unwrap());
+
+    // At once, by chunks crossing the blocks, and by whole blocks
+    for &chunk_size in &[data.len() + 1, 1000, BLK_DATA_SIZE, 1] {
+        assert_eq!(read_all(&mut reader.clone(), chunk_size), data);
+    }
+}
+
+#[test]
+fn test_read_exact_blocks() {
+    // Every direct block is used, then one indirect block is full
+    for &blocks in &[1, MAX_DIRECT_BLK, MAX_DIRECT_BLK + INDIRECT_BLK_CNT] {
+        let data = content(blocks * BLK_DATA_SIZE);
+        let image = Image::new("fs", &[("file", &data)]);
+        let kfs = image.open().expect("Should open");
+        let mut reader = kfs.reader(kfs.find("file").unwrap());
+
+        assert_eq!(read_all(&mut reader, 4096), data);
+    }
+}
+
+#[test]
+fn test_read_indirect_blocks() {
+    let data = content((MAX_DIRECT_BLK + 2 * INDIRECT_BLK_CNT) * BLK_DATA_SIZE + 7);
+    let image = Image::new("fs", &[("big", &data), ("small", b"small")]);
+    let kfs = image.open().expect("Should open");
+
+    let mut reader = kfs.reader(kfs.find("big").unwrap());
+    assert_eq!(read_all(&mut reader, 3000), data);
+
+    let mut reader = kfs.reader(kfs.find("small").unwrap());
+    assert_eq!(read_all(&mut reader, 3000), b"small");
+}
+
+#[test]
+fn test_seek_block_boundaries() {
+    let data = content(3 * BLK_DATA_SIZE + 100);
+    let image = Image::new("fs", &[("file", &data)]);
+    let kfs = image.open().unwrap();
+    let mut reader = kfs.reader(kfs.find("file").unwrap());
+    let mut buffer = [0; 10];
+
+    // At the start of the second block
+    assert_eq!(
+        reader.seek(SeekFrom::Start(BLK_DATA_SIZE)),
+        Ok(BLK_DATA_SIZE)
+    );
+    assert_eq!(reader.read(&mut buffer), Ok(10));
+    assert_eq!(&buffer, &data[BLK_DATA_SIZE..BLK_DATA_SIZE + 10]);
+
+    // Across the end of the first block
+    let start = BLK_DATA_SIZE - 5;
+    assert_eq!(reader.seek(SeekFrom::Start(start)), Ok(start));
+    assert_eq!(reader.read(&mut buffer), Ok(10));
+    assert_eq!(&buffer, &data[start..start + 10]);
+
+    // Back to the previous block, and forward over a whole one
+    let position = start + 10 - BLK_DATA_SIZE;
+    assert_eq!(
+        reader.seek(SeekFrom::Current(-(BLK_DATA_SIZE as isize))),
+        Ok(position)
+    );
+    let position = position + 2 * BLK_DATA_SIZE;
+    assert_eq!(
+        reader.seek(SeekFrom::Current(2 * BLK_DATA_SIZE as isize)),
+        Ok(position)
+    );
+    assert_eq!(reader.read(&mut buffer), Ok(10));
+    assert_eq!(&buffer, &data[position..position + 10]);
+
+    // From the end, in the last block
+    let position = data.len() - 4;
+    assert_eq!(reader.seek(SeekFrom::End(-4)), Ok(position));
+    assert_eq!(reader.read(&mut buffer), Ok(4));
+    assert_eq!(&buffer[..4], &data[position..]);
+}
+
+#[test]
+fn test_seek_out_of_file() {
+    let data = content(BLK_DATA_SIZE + 1);
+    let image = Image::new("fs", &[("file", &data)]);
+    let kfs = image.open().unwrap();
+    let mut reader = kfs.reader(kfs.find("file").unwrap());
+
+    assert_eq!(reader.seek(SeekFrom::Start(10)), Ok(10));
+    assert!(reader.seek(SeekFrom::Current(-11)).is_err());
+    assert!(reader
+        .seek(SeekFrom::End(-(data.len() as isize) - 1))
+        .is_err());
+
+    // Past the end, the position stops at the end
+    assert_eq!(
+        reader.seek(SeekFrom::Start(data.len() + 10)),
+        Ok(data.len())
+    );
+    assert_eq!(reader.read(&mut [0; 10]), Ok(0));
+    assert_eq!(reader.seek(SeekFrom::Start(0)), Ok(0));
+    assert_eq!(read_all(&mut reader, 100), data);
+}
+
+#[test]
+fn test_invalid_superblock() {
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    image.block_mut(0)[0] ^= 1;
+    assert_eq!(image.open().err(), Some(Error::InvalidMagic));
+
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    image.block_mut(0)[4] = b'g';
+    assert_eq!(image.open().err(), Some(Error::InvalidChecksum));
+
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    image.block_mut(0)[4] = 0xFF;
+    seal(image.block_mut(0), SUPERBLOCK_CHECKSUM);
+    assert_eq!(image.open().err(), Some(Error::InvalidName));
+}
+
+#[test]
+fn test_truncated() {
+    let image = Image::new("fs", &[("file", b"content")]);
+    let bytes = image.bytes();
+
+    assert_eq!(
+        Kfs::new(&bytes[..bytes.len() - 1]).err(),
+        Some(Error::MemTooSmall)
+    );
+    assert_eq!(Kfs::new(&bytes[..BLK_SIZE]).err(), Some(Error::MemTooSmall));
+    assert_eq!(Kfs::new(&bytes[..10]).err(), Some(Error::MemTooSmall));
+    assert_eq!(Kfs::new(&bytes[1..]).err(), Some(Error::Misaligned));
+}
+
+#[test]
+fn test_invalid_inode() {
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    image.block_mut(1)[4] = b'F';
+    assert_eq!(image.open().err(), Some(Error::InvalidChecksum));
+
+    // Data block after the end of the image
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    put(image.block_mut(1), INODE_D_BLKS, 100);
+    seal(image.block_mut(1), INODE_CHECKSUM);
+    assert_eq!(image.open().err(), Some(Error::OutOfBounds));
+
+    // More direct blocks than an inode holds
+    let mut image = Image::new("fs", &[("file", b"content")]);
+    put(image.block_mut(1), 52, MAX_DIRECT_BLK as u32 + 1);
+    seal(image.block_mut(1), INODE_CHECKSUM);
+    assert_eq!(image.open().err(), Some(Error::OutOfBounds));
+}
+
+#[test]
+fn test_looping_inodes() {
+    let mut image = Image::new("fs", &[("a", b"a"), ("b", b"b")]);
+    put(image.block_mut(2), INODE_NEXT, 1);
+    seal(image.block_mut(2), INODE_CHECKSUM);
+    assert_eq!(image.open().err(), Some(Error::OutOfBounds));
+}
+
+#[test]
+fn test_invalid_data_block() {
+    let data = content(2 * BLK_DATA_SIZE);
+    let mut image = Image::new("fs", &[("file", &data)]);
+    image.block_mut(3)[100] ^= 1;
+    assert_eq!(image.open().err(), Some(Error::InvalidChecksum));
+
+    // Used size larger than the block
+    let mut image = Image::new("fs", &[("file", &data)]);
+    put(image.block_mut(3), DATA_USAGE, BLK_SIZE as u32);
+    seal_data(image.block_mut(3));
+    assert_eq!(image.open().err(), Some(Error::OutOfBounds));
+}
+
+#[test]
+fn test_invalid_indirect_block() {
+    let data = content((MAX_DIRECT_BLK + 1) * BLK_DATA_SIZE);
+    let indirect = 2 + MAX_DIRECT_BLK + 1;
+
+    let mut image = Image::new("fs", &[("file", &data)]);
+    put(image.block_mut(indirect), 4, INDIRECT_BLK_CNT as u32 + 1);
+    assert_eq!(image.open().err(), Some(Error::InvalidChecksum));
+
+    let mut image = Image::new("fs", &[("file", &data)]);
+    put(image.block_mut(indirect), 4, INDIRECT_BLK_CNT as u32 + 1);
+    seal(image.block_mut(indirect), INDIRECT_CHECKSUM);
+    assert_eq!(image.open().err(), Some(Error::OutOfBounds));
+}
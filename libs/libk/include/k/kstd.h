@@ -28,12 +28,43 @@
 
 typedef s32 ssize_t;
 typedef s32 off_t;
+typedef u32 time_t;
+
+struct timeval {
+	time_t tv_sec;
+	u32 tv_usec;
+};
+
+struct timespec {
+	time_t tv_sec;
+	u32 tv_nsec;
+};
+
+/*
+** a tone without duration is played along the next ones as a chord.
+** melodies end with a null frequency within MELODY_MAX_LEN tones.
+*/
+#define MELODY_MAX_LEN 4096
 
 struct melody {
 	unsigned long freq;
 	unsigned long duration;
 };
 
+struct sound_status {
+	u32 state;
+	u32 priority;
+	u32 frequency;
+	u32 queued;
+};
+
+/* 8 bits samples are unsigned, 16 bits ones are signed. stereo is interleaved */
+struct pcm_format {
+	u32 rate;
+	u16 bits;
+	u16 channels;
+};
+
 /*
 ** constants
 */
@@ -112,7 +143,28 @@ enum e_k_mode {
 /* mouse */
 enum e_mouse_codes {
 	BUTTON_LEFT = 1,
-	BUTTON_RIGHT = 2
+	BUTTON_RIGHT = 2,
+	BUTTON_MIDDLE = 4,
+	WHEEL_UP = 8,
+	WHEEL_DOWN = 16
+};
+
+/* sound */
+enum e_sound_priority {
+	SOUND_MUSIC = 0,
+	SOUND_EFFECT = 1
+};
+
+enum e_sound_command {
+	SOUND_STOP = 0,
+	SOUND_PAUSE = 1,
+	SOUND_RESUME = 2
+};
+
+enum e_sound_state {
+	SOUND_STOPPED = 0,
+	SOUND_PLAYING = 1,
+	SOUND_PAUSED = 2
 };
 
 /* misc */
@@ -122,6 +174,8 @@ enum e_mouse_codes {
 #define SEEK_END	2
 #define VIDEO_GRAPHIC	0
 #define VIDEO_TEXT	1
+#define CLOCK_REALTIME	0
+#define CLOCK_MONOTONIC	1
 
 /*
 ** syscalls
@@ -138,9 +192,21 @@ enum e_mouse_codes {
 #define SYSCALL_SETVIDEO		9
 #define SYSCALL_SWAP_FRONTBUFFER	10
 #define SYSCALL_PLAYSOUND		11
-#define SYSCALL_GETMOUSE		12 /* XXX: not implemented */
+#define SYSCALL_GETMOUSE		12
 #define SYSCALL_GETKEYMODE		13
+#define SYSCALL_TIME			14
+#define SYSCALL_GETTIMEOFDAY		15
+#define SYSCALL_CLOCK_GETTIME		16
+#define SYSCALL_SLEEP			17
+#define SYSCALL_QUEUESOUND		18
+#define SYSCALL_SOUNDCTL		19
+#define SYSCALL_SOUNDSTATUS		20
+#define SYSCALL_PLAYFILE		21
+#define SYSCALL_PCMSUBMIT		22
+#define SYSCALL_DMESG			23
+#define SYSCALL_EXIT			24
+#define SYSCALL_KEYDROPPED		25
 
-#define NR_SYSCALL			(SYSCALL_GETKEYMODE + 1)
+#define NR_SYSCALL			(SYSCALL_KEYDROPPED + 1)
 
 #endif				/* !KSTD_H_ */
@@ -6,7 +6,13 @@
 int write(const void *s, size_t length);
 void *sbrk(ssize_t increment);
 int getkey(void);
+unsigned long keydropped(void);
 unsigned long gettick(void);
+time_t time(time_t *t);
+int gettimeofday(struct timeval *tv, void *tz);
+int clock_gettime(int clock_id, struct timespec *tp);
+int sleep(unsigned long ms);
+int nanosleep(const struct timespec *req);
 int open(const char *pathname, int flags);
 ssize_t read(int fd, void *buf, size_t count);
 off_t seek(int filedes, off_t offset, int whence);
@@ -14,6 +20,14 @@ int close(int fd);
 int setvideo(int mode);
 void swap_frontbuffer(const void *buffer);
 int playsound(struct melody *melody, int repeat);
+int queuesound(struct melody *melody, int repeat, int priority);
+int soundctl(int command);
+int soundstatus(struct sound_status *status);
+int playfile(const char *path, int repeat);
+int pcm_submit(const void *samples, size_t length,
+	       const struct pcm_format *format);
+int dmesg(char *buffer, size_t size);
+void exit(int status);
 int getmouse(int *x, int *y, int *buttons);
 int getkeymode(int mode);
 
@@ -75,11 +75,44 @@ int getkey(void)
 	return ((int)syscall0(SYSCALL_GETKEY));
 }
 
+unsigned long keydropped(void)
+{
+	return ((unsigned long)syscall0(SYSCALL_KEYDROPPED));
+}
+
 unsigned long gettick(void)
 {
 	return ((unsigned long)syscall0(SYSCALL_GETTICK));
 }
 
+time_t time(time_t *t)
+{
+	return ((time_t)syscall1(SYSCALL_TIME, (u32)t));
+}
+
+int gettimeofday(struct timeval *tv, void *tz)
+{
+	(void)tz;
+
+	return ((int)syscall1(SYSCALL_GETTIMEOFDAY, (u32)tv));
+}
+
+int clock_gettime(int clock_id, struct timespec *tp)
+{
+	return ((int)syscall2(SYSCALL_CLOCK_GETTIME, clock_id, (u32)tp));
+}
+
+int sleep(unsigned long ms)
+{
+	return ((int)syscall1(SYSCALL_SLEEP, ms));
+}
+
+int nanosleep(const struct timespec *req)
+{
+	/* the kernel sleeps with a millisecond granularity, round up */
+	return (sleep(req->tv_sec * 1000 + (req->tv_nsec + 999999) / 1000000));
+}
+
 int open(const char *pathname, int flags)
 {
 	return ((int)syscall2(SYSCALL_OPEN, (u32)pathname, flags));
@@ -105,6 +138,43 @@ int playsound(struct melody *melody, int repeat)
 	return ((int)syscall2(SYSCALL_PLAYSOUND, (u32)melody, repeat));
 }
 
+int queuesound(struct melody *melody, int repeat, int priority)
+{
+	return ((int)syscall3(SYSCALL_QUEUESOUND, (u32)melody, repeat, priority));
+}
+
+int soundctl(int command)
+{
+	return ((int)syscall1(SYSCALL_SOUNDCTL, command));
+}
+
+int soundstatus(struct sound_status *status)
+{
+	return ((int)syscall1(SYSCALL_SOUNDSTATUS, (u32)status));
+}
+
+int playfile(const char *path, int repeat)
+{
+	return ((int)syscall2(SYSCALL_PLAYFILE, (u32)path, repeat));
+}
+
+int pcm_submit(const void *samples, size_t length,
+	       const struct pcm_format *format)
+{
+	return ((int)syscall3(SYSCALL_PCMSUBMIT, (u32)samples, length,
+			      (u32)format));
+}
+
+int dmesg(char *buffer, size_t size)
+{
+	return ((int)syscall2(SYSCALL_DMESG, (u32)buffer, size));
+}
+
+void exit(int status)
+{
+	syscall1(SYSCALL_EXIT, status);
+}
+
 int setvideo(int mode)
 {
 	return ((int)syscall1(SYSCALL_SETVIDEO, mode));
@@ -109,6 +109,12 @@ void test_audio(void)
     playsound(intro, -1);
 }
 
+void test_playfile(void)
+{
+    if (playfile("/intro.ksf", 0) < 0)
+        puts("Could not play the file");
+}
+
 void entry(void)
 {
   puts("Start");
@@ -118,6 +124,7 @@ void entry(void)
   // test_sbrk();
   test_video();
   test_audio();
+  test_playfile();
   test_gettick();
 
   puts("Stop");
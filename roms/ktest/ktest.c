@@ -0,0 +1,129 @@
+#include <kstd.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define TEXT "This is the text!\n"
+
+static int failures = 0;
+
+#define check(exp)							\
+    do									\
+    {									\
+        if (!(exp))							\
+        {								\
+            printf("%s, %d: check '%s' failed\n",			\
+                   __BASE_FILE__, __LINE__, #exp);			\
+            failures++;							\
+        }								\
+    }									\
+    while (0)
+
+static void test_write(void)
+{
+    check(write("write\n", 6) == 6);
+}
+
+static void test_sbrk(void)
+{
+    /* The kernel returns the new break */
+    char *brk = sbrk(0);
+
+    check(sbrk(64) == brk + 64);
+    check(sbrk(-64) == brk);
+
+    int *i = malloc(sizeof(int));
+    check(i != NULL);
+    free(i);
+}
+
+static void test_filesystem(void)
+{
+    char buffer[64];
+    int fd = open("text.txt", O_RDONLY);
+
+    check(fd >= 0);
+    if (fd < 0)
+        return;
+
+    check(read(fd, buffer, sizeof(buffer)) == sizeof(TEXT) - 1);
+    check(memcmp(buffer, TEXT, sizeof(TEXT) - 1) == 0);
+
+    check(seek(fd, 5, SEEK_SET) == 5);
+    check(read(fd, buffer, 2) == 2);
+    check(memcmp(buffer, "is", 2) == 0);
+
+    check(close(fd) == 0);
+    check(open("missing.txt", O_RDONLY) < 0);
+}
+
+static void test_time(void)
+{
+    struct timespec before;
+    struct timespec after;
+    unsigned long tick = gettick();
+
+    check(time(NULL) > 0);
+    check(clock_gettime(CLOCK_MONOTONIC, &before) == 0);
+
+    check(sleep(50) == 0);
+
+    check(gettick() - tick >= 50);
+    check(clock_gettime(CLOCK_MONOTONIC, &after) == 0);
+    check(after.tv_sec > before.tv_sec
+          || (after.tv_sec == before.tv_sec && after.tv_nsec > before.tv_nsec));
+}
+
+static void test_dmesg(void)
+{
+    char buffer[256];
+
+    check(dmesg(buffer, sizeof(buffer)) > 0);
+    /* The kernel, loaded at 1 MiB, is not writable */
+    check(dmesg((char *)0x100000, sizeof(buffer)) < 0);
+}
+
+static void test_keyboard(void)
+{
+    /* Nothing is typed during the tests */
+    check(keydropped() == 0);
+}
+
+static void test_playsound(void)
+{
+    /* The melody must be in the memory of the program */
+    check(playsound((struct melody *)0x100000, 0) < 0);
+}
+
+static void test_playfile(void)
+{
+    /* Only errors, so the tests stay silent */
+    check(playfile("/missing.ksf", 0) < 0);
+    check(playfile("/text.txt", 0) < 0);
+}
+
+static void test_pcm_submit(void)
+{
+    struct pcm_format format = { 8000, 8, 1 };
+
+    /* The samples must be in the memory of the program */
+    check(pcm_submit((void *)0x100000, 4096, &format) < 0);
+}
+
+void entry(void)
+{
+    puts("Running the syscall tests");
+
+    test_write();
+    test_sbrk();
+    test_filesystem();
+    test_time();
+    test_dmesg();
+    test_keyboard();
+    test_playsound();
+    test_playfile();
+    test_pcm_submit();
+
+    printf("%d checks failed\n", failures);
+    exit(failures == 0 ? 0 : 1);
+}
@@ -7,7 +7,8 @@ MKKFS	= ../../tools/mkkfs/mkkfs
 all: $(TARGET).rom
 
 $(TARGET): CPPFLAGS += -MMD -I ../../libs/libc/include -I ../../libs/libk/include
-$(TARGET): LDFLAGS += -Wl,-T../roms.lds
+# Position independent, the kernel relocates the ROMs where it loads them
+$(TARGET): LDFLAGS += -static-pie -Wl,-e,entry
 $(TARGET): LDLIBS = -L ../../libs/libk -L ../../libs/libc -Wl,--start-group -lc -lk -Wl,--end-group
 $(TARGET): $(OBJS)
 
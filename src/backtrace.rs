@@ -0,0 +1,280 @@
+//! Symbolic backtraces, following the chain of frame pointers
+//!
+//! Each frame starts with the EBP of the caller, followed by the return address. The
+//! addresses are resolved against the ELF symbol table of the kernel, found through the
+//! multiboot section headers, or against the one of the user program.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ops::Range;
+use core::slice;
+
+use elf::{Elf, ElfSectionHeader, SymbolTable};
+use no_std_io::{Read, Seek};
+
+use crate::multiboot::MultibootInfo;
+use crate::peripherals::serial::SERIAL_PORT;
+use crate::{write_serial, write_vga};
+
+/// Frames printed at most, in case the chain loops
+const MAX_DEPTH: usize = 32;
+/// Frames larger than this mean the chain is broken
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Set once at startup, and only read afterwards
+static mut KERNEL_SYMBOLS: Option<Symbols<'static>> = None;
+
+#[derive(Clone, Copy)]
+pub struct Symbols<'a> {
+    table: SymbolTable<'a>,
+    /// Address at which the ELF is loaded
+    base: usize,
+}
+
+impl<'a> Symbols<'a> {
+    /// Name of the function containing the address, and the offset in it
+    pub fn lookup(&self, address: usize) -> Option<(&'a str, usize)> {
+        let (symbol, offset) = self.table.lookup(address.checked_sub(self.base)?)?;
+        Some((self.table.name(&symbol)?, offset))
+    }
+}
+
+/// Symbols of a user program, read from its file as they are not loaded in memory
+pub struct ProgramSymbols {
+    table: Vec<u8>,
+    strings: Vec<u8>,
+    base: usize,
+}
+
+impl ProgramSymbols {
+    /// Returns None if the program has no symbol table
+    pub fn read<R>(elf: &mut Elf<R>, base: usize) -> Option<Self>
+    where
+        R: Read + Seek,
+    {
+        let sections = elf.symtab().ok()??;
+        // The sizes are checked against the file before allocating
+        let mut table = vec![0; elf.section_size(&sections.symbols).ok()?];
+        let mut strings = vec![0; elf.section_size(&sections.strings).ok()?];
+        elf.read_section(&sections.symbols, &mut table).ok()?;
+        elf.read_section(&sections.strings, &mut strings).ok()?;
+
+        Some(ProgramSymbols {
+            table,
+            strings,
+            base,
+        })
+    }
+
+    pub fn symbols(&self) -> Symbols {
+        Symbols {
+            table: SymbolTable::new(&self.table, &self.strings),
+            base: self.base,
+        }
+    }
+}
+
+/// Find the symbol table of the kernel, which the bootloader loads with the sections
+pub fn init(infos: &MultibootInfo) {
+    let symbols = unsafe { kernel_symbols(infos) };
+    if symbols.is_none() {
+        warn!("No kernel symbol table, backtraces will not be symbolic");
+    }
+
+    unsafe { KERNEL_SYMBOLS = symbols };
+}
+
+unsafe fn kernel_symbols(infos: &MultibootInfo) -> Option<Symbols<'static>> {
+    let (table, link) = infos
+        .elf_sections()
+        .ok()?
+        .find(|s| s.is_symtab())
+        .map(|s| ((s.addr(), s.size()), s.link()))?;
+    let strings = infos
+        .elf_sections()
+        .ok()?
+        .nth(link)
+        .map(|s| (s.addr(), s.size()))?;
+    if table.0 == 0 || strings.0 == 0 {
+        return None;
+    }
+
+    Some(Symbols {
+        table: SymbolTable::new(
+            slice::from_raw_parts(table.0 as *const u8, table.1),
+            slice::from_raw_parts(strings.0 as *const u8, strings.1),
+        ),
+        base: 0,
+    })
+}
+
+/// Print the backtrace of the current kernel function
+pub fn print_kernel() {
+    let ebp: usize;
+    unsafe { llvm_asm!("mov %ebp, $0" : "=r" (ebp) ::: "volatile") };
+
+    let symbols = unsafe { KERNEL_SYMBOLS.as_ref() };
+    print_frames(ebp, 0..::core::usize::MAX, symbols);
+}
+
+/// Print the backtrace of the kernel, interrupted at `eip`
+pub fn print_kernel_from(eip: usize, ebp: usize) {
+    let symbols = unsafe { KERNEL_SYMBOLS.as_ref() };
+    print_address(eip, symbols);
+    print_frames(ebp, 0..::core::usize::MAX, symbols);
+}
+
+/// Print the backtrace of a user program interrupted at `eip`, whose frames are in `stack`
+pub fn print_user_from(eip: usize, ebp: usize, stack: Range<usize>, symbols: Option<&Symbols>) {
+    print_address(eip, symbols);
+    print_frames(ebp, stack, symbols);
+}
+
+fn print_frames(mut ebp: usize, stack: Range<usize>, symbols: Option<&Symbols>) {
+    print(format_args!("Backtrace:\n"));
+
+    for _ in 0..MAX_DEPTH {
+        // Stop at the null EBP set before the first frame, or at a broken chain. The 8 bytes
+        // of the frame must be in the stack, without computing ebp + 8 which may overflow.
+        if ebp == 0 || ebp % 4 != 0 || ebp < stack.start || ebp >= stack.end || stack.end - ebp < 8
+        {
+            break;
+        }
+
+        let (next, address) = unsafe { (*(ebp as *const usize), *((ebp + 4) as *const usize)) };
+        if address == 0 {
+            break;
+        }
+        print_address(address, symbols);
+
+        // The stack grows down, so the callers are above
+        if next <= ebp || next - ebp > MAX_FRAME_SIZE {
+            break;
+        }
+        ebp = next;
+    }
+}
+
+fn print_address(address: usize, symbols: Option<&Symbols>) {
+    match symbols.and_then(|s| s.lookup(address)) {
+        Some((name, offset)) => print(format_args!(
+            "  0x{:08X} {}+0x{:X}\n",
+            address,
+            Demangle(name),
+            offset
+        )),
+        None => print(format_args!("  0x{:08X} ??\n", address)),
+    }
+}
+
+fn print(args: fmt::Arguments) {
+    write_serial!("{}", args);
+    write_vga!("{}", args);
+}
+
+/// Rust symbols in the legacy mangling, `_ZN` followed by the length prefixed path
+/// components and `E`. The last component is a hash, which is not printed.
+struct Demangle<'a>(&'a str);
+
+impl<'a> Demangle<'a> {
+    fn path(&self) -> Option<&'a str> {
+        let name = self.0;
+        if !name.starts_with("_ZN") || !name.ends_with('E') || name.len() < 4 {
+            return None;
+        }
+
+        let path = &name[3..name.len() - 1];
+        let mut rest = path;
+        while !rest.is_empty() {
+            rest = next_component(rest)?.1;
+        }
+
+        Some(path)
+    }
+}
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rest = match self.path() {
+            Some(path) => path,
+            None => return f.write_str(self.0),
+        };
+
+        let mut first = true;
+        while let Some((component, next)) = next_component(rest) {
+            rest = next;
+            if rest.is_empty() && is_hash(component) {
+                break;
+            }
+
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_component(f, component)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Split the first length prefixed component
+fn next_component(path: &str) -> Option<(&str, &str)> {
+    let digits = path.bytes().take_while(|b| b.is_ascii_digit()).count();
+    let length: usize = path[..digits].parse().ok()?;
+    let rest = &path[digits..];
+    if length == 0 || length > rest.len() || !rest.is_char_boundary(length) {
+        return None;
+    }
+
+    Some((&rest[..length], &rest[length..]))
+}
+
+fn is_hash(component: &str) -> bool {
+    component.len() == 17
+        && component.starts_with('h')
+        && component[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Replace the escapes of the characters which can't appear in symbols
+fn write_component(f: &mut fmt::Formatter, component: &str) -> fmt::Result {
+    const ESCAPES: [(&str, &str); 13] = [
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$RF$", "&"),
+        ("$BP$", "*"),
+        ("$SP$", "@"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+    ];
+
+    // Components starting with an escape are prefixed with an underscore
+    let mut rest = if component.starts_with("_$") {
+        &component[1..]
+    } else {
+        component
+    };
+
+    while !rest.is_empty() {
+        if rest.starts_with("..") {
+            f.write_str("::")?;
+            rest = &rest[2..];
+        } else if let Some(&(escape, c)) = ESCAPES.iter().find(|(e, _)| rest.starts_with(e)) {
+            f.write_str(c)?;
+            rest = &rest[escape.len()..];
+        } else {
+            let c = rest.chars().next().unwrap();
+            f.write_char(c)?;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! Kernel command line: the executable to run and its arguments, mixed with `key=value`
+//! kernel options
+//!
+//! The first word is the executable. The other words are kernel options when they contain
+//! a `=`, and arguments of the executable otherwise. Every word after `--` is an argument.
+//!
+//! Options are read through typed parameters, which are all declared and registered in
+//! `startup`, where their values are given to the code using them.
+//! Options matching no registered parameter are reported by `warn_unknown`.
+
+use spin::Mutex;
+
+const MAX_PARAMETERS: usize = 32;
+const ARGUMENTS_SEPARATOR: &str = "--";
+
+static CMDLINE: Mutex<&'static str> = Mutex::new("");
+static PARAMETERS: Mutex<[Option<&'static dyn Parameter>; MAX_PARAMETERS]> =
+    Mutex::new([None; MAX_PARAMETERS]);
+
+/// Type of the value of a parameter
+pub trait Value: Copy + Send + Sync + 'static {
+    /// Returns None if the value is invalid
+    fn parse(value: &'static str) -> Option<Self>;
+}
+
+impl Value for bool {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "on" | "yes" | "true" | "1" => Some(true),
+            "off" | "no" | "false" | "0" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl Value for usize {
+    fn parse(value: &'static str) -> Option<Self> {
+        value.parse().ok()
+    }
+}
+
+impl Value for &'static str {
+    fn parse(value: &'static str) -> Option<Self> {
+        Some(value)
+    }
+}
+
+pub trait Parameter: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns an error if the value is invalid
+    fn set(&self, value: &'static str) -> Result<(), ()>;
+}
+
+/// Parameter read from the `name=value` option, with a default value
+pub struct Param<T> {
+    name: &'static str,
+    default: T,
+    value: Mutex<Option<T>>,
+}
+
+impl<T> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Param {
+            name,
+            default,
+            value: Mutex::new(None),
+        }
+    }
+}
+
+impl<T: Value> Param<T> {
+    /// Returns the value of the option, or the default one if it is absent or invalid
+    pub fn get(&self) -> T {
+        self.value.lock().unwrap_or(self.default)
+    }
+}
+
+impl<T: Value> Parameter for Param<T> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn set(&self, value: &'static str) -> Result<(), ()> {
+        let value = T::parse(value).ok_or(())?;
+        self.value.lock().replace(value);
+        Ok(())
+    }
+}
+
+pub fn init(cmdline: &'static str) {
+    *CMDLINE.lock() = cmdline;
+}
+
+/// Returns the executable, which is the first word of the command line
+pub fn executable() -> Option<&'static str> {
+    words().next()
+}
+
+/// Returns the arguments of the executable
+pub fn arguments() -> impl Iterator<Item = &'static str> {
+    let mut separated = false;
+    words().skip(1).filter(move |&word| {
+        if !separated && word == ARGUMENTS_SEPARATOR {
+            separated = true;
+            return false;
+        }
+        separated || !word.contains('=')
+    })
+}
+
+/// Returns the kernel options, as keys and values
+fn options() -> impl Iterator<Item = (&'static str, &'static str)> {
+    words()
+        .skip(1)
+        .take_while(|&word| word != ARGUMENTS_SEPARATOR)
+        .filter_map(|word| {
+            let mut split = word.splitn(2, '=');
+            Some((split.next()?, split.next()?))
+        })
+}
+
+fn words() -> impl Iterator<Item = &'static str> {
+    let cmdline: &'static str = *CMDLINE.lock();
+    cmdline.split_whitespace()
+}
+
+/// Set the parameter from its option, the last one if it is given several times
+pub fn register(parameter: &'static dyn Parameter) {
+    {
+        let mut parameters = PARAMETERS.lock();
+        match parameters.iter_mut().find(|p| p.is_none()) {
+            Some(slot) => *slot = Some(parameter),
+            None => warn!(
+                "Too many parameters, {} is not registered",
+                parameter.name()
+            ),
+        }
+    }
+
+    for (_, value) in options().filter(|&(key, _)| key == parameter.name()) {
+        if parameter.set(value).is_err() {
+            warn!(
+                "Invalid value \"{}\" for the option {}",
+                value,
+                parameter.name()
+            );
+        }
+    }
+}
+
+/// Warn about the options matching no registered parameter
+pub fn warn_unknown() {
+    let parameters = PARAMETERS.lock();
+    for (key, _) in options() {
+        if !parameters.iter().flatten().any(|p| p.name() == key) {
+            warn!("Unknown option {}", key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn parse_values() {
+        assert_eq!(bool::parse("on"), Some(true));
+        assert_eq!(bool::parse("0"), Some(false));
+        assert_eq!(bool::parse("maybe"), None);
+        assert_eq!(usize::parse("100"), Some(100));
+        assert_eq!(usize::parse("-1"), None);
+    }
+
+    #[test_case]
+    fn unset_parameter_has_default() {
+        static PARAM: Param<usize> = Param::new("unset_test_parameter", 42);
+        register(&PARAM);
+        assert_eq!(PARAM.get(), 42);
+
+        assert!(PARAM.set("invalid").is_err());
+        assert_eq!(PARAM.get(), 42);
+        assert!(PARAM.set("7").is_ok());
+        assert_eq!(PARAM.get(), 7);
+    }
+}
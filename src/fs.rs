@@ -0,0 +1,78 @@
+//! The KFS loaded by the bootloader as a multiboot module
+
+use core::slice;
+
+use kfs::{Kfs, Result};
+
+use crate::peripherals::rtc::DateTime;
+
+/// Set once at startup, and only read afterwards
+static mut FS: Option<Kfs<'static>> = None;
+
+pub unsafe fn init(start: u32, end: u32) -> Result<()> {
+    info!(
+        "KFS loaded between 0x{:X} and 0x{:X} ({} bytes)",
+        start,
+        end,
+        end - start
+    );
+
+    let data = slice::from_raw_parts(start as *const u8, (end - start) as usize);
+    let fs = Kfs::new(data)?;
+    info!(
+        "KFS \"{}\" created on {}",
+        fs.name(),
+        DateTime::from_timestamp(fs.ctime())
+    );
+
+    FS = Some(fs);
+    Ok(())
+}
+
+pub fn get() -> Kfs<'static> {
+    try_get().expect("KFS was not initialized")
+}
+
+/// Returns None if no file system was loaded
+pub fn try_get() -> Option<Kfs<'static>> {
+    unsafe { FS }
+}
+
+/// The test ROM holds `text.txt`
+#[cfg(test)]
+mod tests {
+    use no_std_io::{Read, Seek, SeekFrom};
+
+    use super::*;
+
+    const TEXT: &[u8] = b"This is the text!\n";
+
+    #[test_case]
+    fn find_and_read() {
+        let fs = try_get().expect("The tests need a KFS module");
+        assert!(fs.find("/missing.txt").is_none());
+
+        let mut file = fs.reader(fs.find("/text.txt").expect("text.txt"));
+        let mut buffer = [0; 64];
+        assert_eq!(file.read(&mut buffer), Ok(TEXT.len()));
+        assert_eq!(&buffer[..TEXT.len()], TEXT);
+        assert_eq!(file.read(&mut buffer), Ok(0));
+    }
+
+    #[test_case]
+    fn seek() {
+        let fs = try_get().expect("The tests need a KFS module");
+        let mut file = fs.reader(fs.find("/text.txt").expect("text.txt"));
+        let mut buffer = [0; 2];
+
+        assert_eq!(file.seek(SeekFrom::Start(5)), Ok(5));
+        assert_eq!(file.read(&mut buffer), Ok(2));
+        assert_eq!(&buffer, b"is");
+
+        assert_eq!(file.seek(SeekFrom::End(-5)), Ok(TEXT.len() - 5));
+        assert_eq!(file.read(&mut buffer), Ok(2));
+        assert_eq!(&buffer, b"te");
+
+        assert!(file.seek(SeekFrom::Current(-100)).is_err());
+    }
+}
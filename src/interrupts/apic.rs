@@ -0,0 +1,154 @@
+//! Interrupt delivery through the local APIC and the I/O APIC, replacing the 8259
+//!
+//! The ISA IRQs keep the vectors they have with the 8259, so the same entry points
+//! serve both controllers.
+
+use spin::Mutex;
+
+use super::tables::{self, Route};
+use crate::arch::i386::apic::{IoApic, LocalApic};
+use crate::arch::i386::instructions::cpuid;
+use crate::arch::i386::instructions::Port;
+use crate::arch::i386::pic::{Pic, PIC};
+use crate::arch::i386::pit::{Pit, PIT};
+
+/// Vector of the spurious interrupts. Its low bits must be set on old local APICs.
+pub const SPURIOUS_VECTOR: u8 = 0x4F;
+/// Vector of the local APIC timer, between the IRQs of the master and the slave 8259
+pub const TIMER_VECTOR: u8 = 0x48;
+
+const IMCR_SELECT: u16 = 0x22;
+const IMCR_DATA: u16 = 0x23;
+const IMCR_REGISTER: u8 = 0x70;
+const IMCR_APIC: u8 = 0x01;
+
+/// Duration of the timer calibration, in PIT cycles (10 ms)
+const CALIBRATION_CYCLES: u16 = 11932;
+
+static APIC: Mutex<Option<Apic>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct Apic {
+    local: LocalApic,
+    io: IoApic,
+    routes: [Route; Pic::IRQ_COUNT as usize],
+    /// Local APIC timer cycles per second, once divided
+    timer_frequency: u32,
+}
+
+/// Switch to the APIC if the CPU and the firmware tables have one.
+/// Returns false if the 8259 must be used instead.
+pub fn init() -> bool {
+    if !cpuid::has_apic() {
+        info!("No local APIC, using the 8259 PIC");
+        return false;
+    }
+
+    let topology = match tables::find() {
+        Some(topology) => topology,
+        None => {
+            info!("No I/O APIC in the ACPI or MP tables, using the 8259 PIC");
+            return false;
+        }
+    };
+
+    // The 8259 is still remapped, so its spurious IRQs don't look like exceptions
+    PIC.lock().mask_all();
+    if topology.has_imcr {
+        unsafe {
+            Port::<u8>::new(IMCR_SELECT).write(IMCR_REGISTER);
+            Port::<u8>::new(IMCR_DATA).write(IMCR_APIC);
+        }
+    }
+
+    let local_base = unsafe { LocalApic::base() };
+    let local = unsafe { LocalApic::new(local_base) };
+    let io = unsafe { IoApic::new(topology.io_apic) };
+    unsafe { local.enable(SPURIOUS_VECTOR) };
+
+    let destination = local.id();
+    for input in 0..io.inputs() {
+        io.mask(input);
+    }
+    let routes = &topology.routes;
+    for (irq, route) in routes.iter().enumerate() {
+        // An IRQ overridden to the input of another one takes its place, like the PIT
+        let taken = routes
+            .iter()
+            .enumerate()
+            .any(|(other, r)| other != irq && r.input == route.input && r.input as usize != other);
+        if irq as u8 == Pic::CASCADE_IRQ || taken {
+            continue;
+        }
+
+        io.set_redirection(
+            route.input,
+            Pic::interrupt(irq as u8),
+            route.polarity,
+            route.trigger,
+            destination,
+        );
+    }
+
+    let timer_frequency = calibrate_timer(&local);
+    info!(
+        "Local APIC at 0x{:X}, I/O APIC at 0x{:X}, timer at {} kHz",
+        local_base,
+        topology.io_apic,
+        timer_frequency / 1000
+    );
+
+    APIC.lock().replace(Apic {
+        local,
+        io,
+        routes: topology.routes,
+        timer_frequency,
+    });
+
+    true
+}
+
+/// Count the timer cycles during a known number of PIT cycles
+fn calibrate_timer(local: &LocalApic) -> u32 {
+    local.start_timer_count(::core::u32::MAX);
+    PIT.lock().wait(CALIBRATION_CYCLES);
+    let elapsed = ::core::u32::MAX - local.timer_count();
+    local.stop_timer();
+
+    (elapsed as u64 * Pit::FREQUENCY as u64 / CALIBRATION_CYCLES as u64) as u32
+}
+
+pub fn is_enabled() -> bool {
+    APIC.lock().is_some()
+}
+
+pub fn unmask(irq: u8) {
+    if let Some(ref apic) = *APIC.lock() {
+        apic.io.unmask(apic.routes[irq as usize].input);
+    }
+}
+
+pub fn mask(irq: u8) {
+    if let Some(ref apic) = *APIC.lock() {
+        apic.io.mask(apic.routes[irq as usize].input);
+    }
+}
+
+pub fn end_of_interrupt() {
+    if let Some(ref apic) = *APIC.lock() {
+        apic.local.end_of_interrupt();
+    }
+}
+
+/// Raise the timer vector `rate` times per second.
+/// Returns an error if the APIC is not used.
+pub fn start_timer(rate: usize) -> Result<(), ()> {
+    match *APIC.lock() {
+        Some(ref apic) => {
+            let count = apic.timer_frequency / rate as u32;
+            apic.local.set_timer_periodic(TIMER_VECTOR, count.max(1));
+            Ok(())
+        }
+        None => Err(()),
+    }
+}
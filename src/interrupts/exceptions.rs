@@ -0,0 +1,84 @@
+//! CPU exceptions: a fault stops the user process, or the kernel, with a backtrace,
+//! unless the debugger handles it
+
+use core::fmt::Write;
+
+use super::{gdb, InterruptContext};
+use crate::backtrace;
+use crate::peripherals::serial::SERIAL_PORT;
+use crate::userland::USER_PROCESS;
+use crate::{write_serial, write_vga};
+
+/// Exceptions are the first vectors
+pub const COUNT: u32 = 20;
+
+const NAMES: [&str; COUNT as usize] = [
+    "Divide error",
+    "Debug",
+    "Non-maskable interrupt",
+    "Breakpoint",
+    "Overflow",
+    "BOUND range exceeded",
+    "Invalid opcode",
+    "Device not available",
+    "Double fault",
+    "Coprocessor segment overrun",
+    "Invalid TSS",
+    "Segment not present",
+    "Stack-segment fault",
+    "General protection fault",
+    "Page fault",
+    "Reserved",
+    "x87 floating-point error",
+    "Alignment check",
+    "Machine check",
+    "SIMD floating-point exception",
+];
+
+pub fn exception_handler(context: &mut InterruptContext) {
+    // The debugger resumes the execution once done
+    if !gdb::handle_exception(context) {
+        fault(context);
+    }
+}
+
+#[allow(safe_packed_borrows)]
+fn fault(context: &InterruptContext) -> ! {
+    let name = NAMES[context.interrupt_number as usize];
+    let (eip, ebp) = (context.eip as usize, context.ebp as usize);
+    let from_user = context.cs & 0b11 != 0;
+
+    let origin = if from_user { "user process" } else { "kernel" };
+    write_serial!(
+        "[ERROR] {} in the {} at 0x{:08X}, error code 0x{:X}\n",
+        name,
+        origin,
+        eip,
+        context.error_code
+    );
+    write_vga!(
+        "[ERROR] {} in the {} at 0x{:08X}, error code 0x{:X}\n",
+        name,
+        origin,
+        eip,
+        context.error_code
+    );
+
+    if from_user {
+        // The process never holds the lock while it runs
+        match USER_PROCESS.try_lock() {
+            Some(process) => backtrace::print_user_from(
+                eip,
+                ebp,
+                process.stack.clone(),
+                process.symbols.as_ref().map(|s| s.symbols()).as_ref(),
+            ),
+            None => backtrace::print_user_from(eip, ebp, 0..0, None),
+        }
+        error!("The user process was stopped");
+    } else {
+        backtrace::print_kernel_from(eip, ebp);
+    }
+
+    crate::abort();
+}
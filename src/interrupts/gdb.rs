@@ -0,0 +1,579 @@
+//! GDB remote serial protocol stub on COM2
+//!
+//! When enabled, the CPU exceptions stop in the stub instead of killing the faulting code,
+//! and GDB can interrupt the system with Ctrl-C. Breakpoints are `int3` instructions
+//! written over the code, and single steps use the trap flag.
+//!
+//! With QEMU, `-serial stdio -serial tcp::1234,server,nowait` and `gdb=on` on the kernel
+//! command line, then `target remote localhost:1234` in GDB. With `gdb=wait`, the kernel
+//! stops during the startup until GDB is attached and continues.
+
+use core::fmt::{self, Write};
+use core::mem::{replace, size_of};
+use core::ptr::{read_volatile, write_volatile};
+
+use spin::Mutex;
+
+use super::InterruptContext;
+use crate::cmdline;
+use crate::memory::KERNEL_DATA_SEGMENT;
+use crate::peripherals::serial::{SerialPort, COM2};
+
+const IRQ: u8 = 3;
+/// Sent by GDB on Ctrl-C
+const INTERRUPT: u8 = 0x03;
+
+/// Size of the packets, without the framing
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+const INT3: u8 = 0xCC;
+const TRAP_FLAG: u32 = 1 << 8;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// Registers sent by GDB for i386: eax, ecx, edx, ebx, esp, ebp, esi, edi, eip, eflags,
+/// cs, ss, ds, es, fs and gs
+const REGISTER_COUNT: usize = 16;
+
+static STUB: Mutex<Option<Stub>> = Mutex::new(None);
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    address: usize,
+    /// Byte replaced by the `int3`
+    original: u8,
+}
+
+struct Stub {
+    port: SerialPort,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// GDB is attached, and waits for a stop reply when the execution stops
+    attached: bool,
+    /// GDB interrupted the execution, which is reported as SIGINT
+    interrupted: bool,
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    On,
+    /// Stop during the startup until GDB continues
+    Wait,
+}
+
+impl cmdline::Value for Mode {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "off" => Some(Mode::Off),
+            "on" => Some(Mode::On),
+            "wait" => Some(Mode::Wait),
+            _ => None,
+        }
+    }
+}
+
+/// What to do after a packet
+enum Action {
+    Reply,
+    /// Resume the execution, with the trap flag or not
+    Resume {
+        step: bool,
+    },
+}
+
+/// Start the stub on COM2, and stop right away in `Wait` mode
+pub fn init(mode: Mode) {
+    if mode == Mode::Off {
+        return;
+    }
+
+    let mut port = SerialPort::new_uart_16550(COM2);
+    port.enable_receive_interrupt();
+
+    STUB.lock().replace(Stub {
+        port,
+        breakpoints: [None; MAX_BREAKPOINTS],
+        attached: false,
+        interrupted: false,
+        packet: [0; PACKET_SIZE],
+        reply: Reply::new(),
+    });
+
+    if super::register_irq(IRQ, on_interrupt).is_err() {
+        warn!("IRQ {} is taken, GDB can't interrupt the execution", IRQ);
+    }
+    info!("GDB stub listening on COM2");
+
+    if mode == Mode::Wait {
+        info!("Waiting for GDB...");
+        breakpoint();
+    }
+}
+
+/// Stop in the debugger
+pub fn breakpoint() {
+    unsafe { llvm_asm!("int3" :::: "volatile") };
+}
+
+/// Called when COM2 receives bytes, which are only Ctrl-C while the execution runs
+fn on_interrupt() {
+    let interrupted = match *STUB.lock() {
+        Some(ref mut stub) => {
+            while let Some(byte) = stub.port.read_byte() {
+                stub.interrupted |= byte == INTERRUPT;
+            }
+            stub.interrupted
+        }
+        None => false,
+    };
+
+    // The lock must be released before the exception
+    if interrupted {
+        breakpoint();
+    }
+}
+
+/// Report the exception to GDB and serve its requests until it resumes the execution.
+/// Returns false if the stub is not enabled, so the exception must be handled as a fault.
+pub fn handle_exception(context: &mut InterruptContext) -> bool {
+    // The stub itself may have faulted
+    match STUB.try_lock() {
+        Some(mut stub) => match *stub {
+            Some(ref mut stub) => {
+                stub.handle_exception(context);
+                true
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+impl Stub {
+    fn handle_exception(&mut self, context: &mut InterruptContext) {
+        let vector = context.interrupt_number;
+
+        // Report the breakpoints at their address, rather than after the int3
+        let eip = context.eip as usize;
+        if vector == 3
+            && self
+                .breakpoints
+                .iter()
+                .flatten()
+                .any(|b| b.address == eip.wrapping_sub(1))
+        {
+            context.eip -= 1;
+        }
+
+        let signal = if replace(&mut self.interrupted, false) {
+            SIGINT
+        } else {
+            signal_of(vector)
+        };
+
+        if self.attached {
+            self.reply.clear();
+            write!(self.reply, "S{:02x}", signal).ok();
+            self.send_reply();
+        }
+
+        loop {
+            let length = self.receive();
+            self.attached = true;
+            self.reply.clear();
+
+            // Copy the packet, as replying borrows the stub
+            let mut packet = [0; PACKET_SIZE];
+            packet[..length].copy_from_slice(&self.packet[..length]);
+
+            match self.execute(&packet[..length], context, signal) {
+                Action::Reply => self.send_reply(),
+                Action::Resume { step } => {
+                    if step {
+                        context.eflags |= TRAP_FLAG;
+                    } else {
+                        context.eflags &= !TRAP_FLAG;
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Execute the command of the packet, and fill the reply.
+    /// Unsupported commands have an empty reply.
+    fn execute(&mut self, packet: &[u8], context: &mut InterruptContext, signal: u8) -> Action {
+        let (command, arguments) = match packet.split_first() {
+            Some((&command, arguments)) => (command, arguments),
+            None => return Action::Reply,
+        };
+
+        match command {
+            b'?' => {
+                write!(self.reply, "S{:02x}", signal).ok();
+            }
+            b'g' => {
+                for register in 0..REGISTER_COUNT {
+                    self.reply.u32(read_register(context, register));
+                }
+            }
+            b'G' => {
+                for (register, value) in arguments.chunks(8).enumerate().take(REGISTER_COUNT) {
+                    if let Some(value) = parse_u32_le(value) {
+                        write_register(context, register, value);
+                    }
+                }
+                self.reply.ok();
+            }
+            b'p' => match parse_hex(arguments) {
+                Some(register) if register < REGISTER_COUNT => {
+                    self.reply.u32(read_register(context, register))
+                }
+                _ => self.reply.error(),
+            },
+            b'P' => {
+                let mut split = arguments.splitn(2, |&b| b == b'=');
+                let register = split.next().and_then(parse_hex);
+                let value = split.next().and_then(parse_u32_le);
+                match (register, value) {
+                    (Some(register), Some(value)) if register < REGISTER_COUNT => {
+                        write_register(context, register, value);
+                        self.reply.ok();
+                    }
+                    _ => self.reply.error(),
+                }
+            }
+            b'm' => match parse_range(arguments) {
+                // Each byte takes two characters in the reply
+                Some((address, length)) if length <= PACKET_SIZE / 2 => {
+                    for offset in 0..length {
+                        let byte = unsafe { read_volatile((address + offset) as *const u8) };
+                        self.reply.u8(byte);
+                    }
+                }
+                _ => self.reply.error(),
+            },
+            b'M' => {
+                let mut split = arguments.splitn(2, |&b| b == b':');
+                let range = split.next().and_then(parse_range);
+                let data = split.next().unwrap_or(&[]);
+                match range {
+                    Some((address, length)) if data.len() == length * 2 => {
+                        for (offset, byte) in data.chunks(2).enumerate() {
+                            if let Some(byte) = parse_hex(byte) {
+                                unsafe {
+                                    write_volatile((address + offset) as *mut u8, byte as u8)
+                                };
+                            }
+                        }
+                        self.reply.ok();
+                    }
+                    _ => self.reply.error(),
+                }
+            }
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(arguments) {
+                    context.eip = address as u32;
+                }
+                return Action::Resume {
+                    step: command == b's',
+                };
+            }
+            b'Z' | b'z' if arguments.starts_with(b"0,") => {
+                let address = parse_range(&arguments[2..]).map(|(address, _)| address);
+                let result = match address {
+                    Some(address) if command == b'Z' => self.insert_breakpoint(address),
+                    Some(address) => self.remove_breakpoint(address),
+                    None => Err(()),
+                };
+                match result {
+                    Ok(()) => self.reply.ok(),
+                    Err(()) => self.reply.error(),
+                }
+            }
+            b'D' | b'k' => {
+                self.remove_breakpoints();
+                self.attached = false;
+                if command == b'D' {
+                    self.reply.ok();
+                    self.send_reply();
+                }
+                return Action::Resume { step: false };
+            }
+            b'H' => self.reply.ok(),
+            b'q' if arguments.starts_with(b"Supported") => {
+                write!(self.reply, "PacketSize={:x}", PACKET_SIZE).ok();
+            }
+            b'q' if arguments == b"Attached" => {
+                self.reply.write_str("1").ok();
+            }
+            _ => (),
+        }
+
+        Action::Reply
+    }
+
+    fn insert_breakpoint(&mut self, address: usize) -> Result<(), ()> {
+        if self
+            .breakpoints
+            .iter()
+            .flatten()
+            .any(|b| b.address == address)
+        {
+            return Ok(());
+        }
+
+        let slot = self
+            .breakpoints
+            .iter_mut()
+            .find(|b| b.is_none())
+            .ok_or(())?;
+        let original = unsafe { read_volatile(address as *const u8) };
+        unsafe { write_volatile(address as *mut u8, INT3) };
+        *slot = Some(Breakpoint { address, original });
+
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, address: usize) -> Result<(), ()> {
+        let slot = self
+            .breakpoints
+            .iter_mut()
+            .find(|b| b.map(|b| b.address == address).unwrap_or(false))
+            .ok_or(())?;
+
+        if let Some(breakpoint) = slot.take() {
+            unsafe { write_volatile(address as *mut u8, breakpoint.original) };
+        }
+
+        Ok(())
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                unsafe { write_volatile(breakpoint.address as *mut u8, breakpoint.original) };
+            }
+        }
+    }
+
+    /// Wait for a valid packet, and returns its length
+    fn receive(&mut self) -> usize {
+        loop {
+            while self.port.wait_byte() != b'$' {}
+
+            let mut length = 0;
+            let mut checksum = 0u8;
+            let mut overflow = false;
+            loop {
+                let byte = self.port.wait_byte();
+                if byte == b'#' {
+                    break;
+                }
+
+                checksum = checksum.wrapping_add(byte);
+                if length < PACKET_SIZE {
+                    self.packet[length] = byte;
+                    length += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+
+            let expected = [self.port.wait_byte(), self.port.wait_byte()];
+            if !overflow && parse_hex(&expected) == Some(checksum as usize) {
+                self.port.write_byte(b'+');
+                return length;
+            }
+
+            self.port.write_byte(b'-');
+        }
+    }
+
+    /// Send the reply until GDB acknowledges it
+    fn send_reply(&mut self) {
+        let reply = &self.reply.buffer[..self.reply.length];
+        let checksum = reply.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+        loop {
+            self.port.write_byte(b'$');
+            for &byte in reply {
+                self.port.write_byte(byte);
+            }
+            self.port.write_byte(b'#');
+            self.port.write_byte(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.port.write_byte(HEX_DIGITS[(checksum & 0xF) as usize]);
+
+            match self.port.wait_byte() {
+                b'-' => continue,
+                _ => return,
+            }
+        }
+    }
+}
+
+fn signal_of(vector: u32) -> u8 {
+    match vector {
+        0 | 16 | 19 => SIGFPE,
+        6 => SIGILL,
+        17 => SIGBUS,
+        4 | 5 | 10..=14 => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+/// The stack pointer and the stack segment are only pushed when coming from the user
+/// mode, otherwise the stack continues right after the context
+fn stack_of(context: &InterruptContext) -> (*mut u32, bool) {
+    let end = context as *const InterruptContext as usize + size_of::<InterruptContext>();
+    (end as *mut u32, context.cs & 0b11 != 0)
+}
+
+fn read_register(context: &InterruptContext, register: usize) -> u32 {
+    match register {
+        0 => context.eax,
+        1 => context.ecx,
+        2 => context.edx,
+        3 => context.ebx,
+        4 => match stack_of(context) {
+            (stack, true) => unsafe { *stack },
+            (stack, false) => stack as u32,
+        },
+        5 => context.ebp,
+        6 => context.esi,
+        7 => context.edi,
+        8 => context.eip,
+        9 => context.eflags,
+        10 => context.cs,
+        11 => match stack_of(context) {
+            (stack, true) => unsafe { *stack.add(1) },
+            (_, false) => KERNEL_DATA_SEGMENT as u32,
+        },
+        12 => context.ds as u32,
+        13 => context.es as u32,
+        14 => context.fs as u32,
+        15 => context.gs as u32,
+        _ => 0,
+    }
+}
+
+/// Segments, and the stack pointer of the kernel, can't be changed
+fn write_register(context: &mut InterruptContext, register: usize, value: u32) {
+    match register {
+        0 => context.eax = value,
+        1 => context.ecx = value,
+        2 => context.edx = value,
+        3 => context.ebx = value,
+        4 => {
+            if let (stack, true) = stack_of(context) {
+                unsafe { *stack = value };
+            }
+        }
+        5 => context.ebp = value,
+        6 => context.esi = value,
+        7 => context.edi = value,
+        8 => context.eip = value,
+        9 => context.eflags = value,
+        _ => (),
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Reply being built, to be resent if it is corrupted
+struct Reply {
+    buffer: [u8; PACKET_SIZE],
+    length: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply {
+            buffer: [0; PACKET_SIZE],
+            length: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.length = 0;
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.length < PACKET_SIZE {
+            self.buffer[self.length] = byte;
+            self.length += 1;
+        }
+    }
+
+    fn u8(&mut self, byte: u8) {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]);
+        self.push(HEX_DIGITS[(byte & 0xF) as usize]);
+    }
+
+    /// Registers are in the byte order of the target
+    fn u32(&mut self, value: u32) {
+        for &byte in value.to_le_bytes().iter() {
+            self.u8(byte);
+        }
+    }
+
+    fn ok(&mut self) {
+        self.write_str("OK").ok();
+    }
+
+    fn error(&mut self) {
+        self.write_str("E01").ok();
+    }
+}
+
+impl fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_hex(digits: &[u8]) -> Option<usize> {
+    if digits.is_empty() || digits.len() > 2 * size_of::<usize>() {
+        return None;
+    }
+
+    digits.iter().try_fold(0, |value, &digit| {
+        let digit = (digit as char).to_digit(16)?;
+        Some(value << 4 | digit as usize)
+    })
+}
+
+/// `address,length`
+fn parse_range(arguments: &[u8]) -> Option<(usize, usize)> {
+    let mut split = arguments.splitn(2, |&b| b == b',');
+    let address = parse_hex(split.next()?)?;
+    let length = parse_hex(split.next()?)?;
+    address.checked_add(length)?;
+
+    Some((address, length))
+}
+
+/// 8 digits of a little endian value
+fn parse_u32_le(digits: &[u8]) -> Option<u32> {
+    if digits.len() != 8 {
+        return None;
+    }
+
+    let mut bytes = [0; 4];
+    for (byte, digits) in bytes.iter_mut().zip(digits.chunks(2)) {
+        *byte = parse_hex(digits)? as u8;
+    }
+
+    Some(u32::from_le_bytes(bytes))
+}
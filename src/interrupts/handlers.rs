@@ -1,29 +1,14 @@
+use core::mem::size_of;
 use core::slice;
 
 use no_std_io::SeekFrom;
 
-use crate::arch::i386::instructions::Port;
-use crate::arch::i386::pic::PIC;
-use crate::peripherals::keyboard;
+use crate::peripherals::mouse;
+use crate::peripherals::sb16;
 use crate::peripherals::speaker;
-use crate::peripherals::timer;
 
 use super::InterruptContext;
 
-pub fn pit_handler(_context: &mut InterruptContext) {
-    timer::tick();
-    PIC.lock().send_eoi_to_master();
-}
-
-pub fn keyboard_handler(_context: &mut InterruptContext) {
-    let is_full = (unsafe { Port::<u8>::new(0x64).read() } & 0x1) == 1;
-    if is_full {
-        let scan = unsafe { Port::new(0x60).read() };
-        keyboard::receive_scan(scan);
-    }
-    PIC.lock().send_eoi_to_master();
-}
-
 // TODO Use bingen ?
 const SYSCALL_WRITE: u32 = 1;
 const SYSCALL_SBRK: u32 = 2;
@@ -36,6 +21,19 @@ const SYSCALL_CLOSE: u32 = 8;
 const SYSCALL_SETVIDEO: u32 = 9;
 const SYSCALL_SWAPFRONTBUFFER: u32 = 10;
 const SYSCALL_PLAYSOUND: u32 = 11;
+const SYSCALL_GETMOUSE: u32 = 12;
+const SYSCALL_TIME: u32 = 14;
+const SYSCALL_GETTIMEOFDAY: u32 = 15;
+const SYSCALL_CLOCK_GETTIME: u32 = 16;
+const SYSCALL_SLEEP: u32 = 17;
+const SYSCALL_QUEUESOUND: u32 = 18;
+const SYSCALL_SOUNDCTL: u32 = 19;
+const SYSCALL_SOUNDSTATUS: u32 = 20;
+const SYSCALL_PLAYFILE: u32 = 21;
+const SYSCALL_PCMSUBMIT: u32 = 22;
+const SYSCALL_DMESG: u32 = 23;
+const SYSCALL_EXIT: u32 = 24;
+const SYSCALL_KEYDROPPED: u32 = 25;
 
 // TODO Check pointers come from userland, and copy them ?
 #[allow(safe_packed_borrows)]
@@ -45,10 +43,35 @@ pub fn syscall_handler(context: &mut InterruptContext) {
         SYSCALL_WRITE => syscall_write(context.ebx as *const u8, context.ecx as usize),
         SYSCALL_SBRK => syscall_sbrk(context.ebx as isize),
         SYSCALL_GETKEY => syscall_getkey(),
+        SYSCALL_KEYDROPPED => syscall_keydropped(),
         SYSCALL_GETTICK => syscall_gettick(),
+        SYSCALL_TIME => syscall_time(context.ebx as *mut u32),
+        SYSCALL_GETTIMEOFDAY => syscall_gettimeofday(context.ebx as *mut TimeVal),
+        SYSCALL_SLEEP => syscall_sleep(context.ebx as usize),
+        SYSCALL_CLOCK_GETTIME => {
+            syscall_clock_gettime(context.ebx, context.ecx as *mut TimeSpec)
+        }
         SYSCALL_PLAYSOUND => {
             syscall_playsound(context.ebx as *const speaker::Tone, context.ecx != 0)
         }
+        SYSCALL_QUEUESOUND => syscall_queuesound(
+            context.ebx as *const speaker::Tone,
+            context.ecx != 0,
+            context.edx,
+        ),
+        SYSCALL_SOUNDCTL => syscall_soundctl(context.ebx),
+        SYSCALL_SOUNDSTATUS => syscall_soundstatus(context.ebx as *mut SoundStatus),
+        SYSCALL_PLAYFILE => syscall_playfile(
+            unsafe { crate::strings::cstr_to_str_unchecked(context.ebx as *const u8) },
+            context.ecx != 0,
+        ),
+        SYSCALL_PCMSUBMIT => syscall_pcmsubmit(
+            context.ebx as *const u8,
+            context.ecx as usize,
+            context.edx as *const PcmFormat,
+        ),
+        SYSCALL_DMESG => syscall_dmesg(context.ebx as *mut u8, context.ecx as usize),
+        SYSCALL_EXIT => syscall_exit(context.ebx as i32),
         SYSCALL_OPEN => syscall_open(unsafe { crate::strings::cstr_to_str_unchecked(context.ebx as *const u8) }, context.ecx),
         SYSCALL_READ => syscall_read(context.ebx, unsafe {
             slice::from_raw_parts_mut(context.ecx as *mut u8, context.edx as usize)
@@ -57,6 +80,11 @@ pub fn syscall_handler(context: &mut InterruptContext) {
         SYSCALL_CLOSE => syscall_close(context.ebx),
         SYSCALL_SETVIDEO => syscall_setvideo(context.ebx),
         SYSCALL_SWAPFRONTBUFFER => syscall_swapfrontbuffer(context.ebx as *const u8),
+        SYSCALL_GETMOUSE => syscall_getmouse(
+            context.ebx as *mut i32,
+            context.ecx as *mut i32,
+            context.edx as *mut i32,
+        ),
         _ => ::core::u32::MAX,
     };
 
@@ -65,6 +93,20 @@ pub fn syscall_handler(context: &mut InterruptContext) {
     context.eax = ret;
 }
 
+/// The program must not make the kernel write outside of its writable memory
+fn is_writable<T>(pointer: *mut T, count: usize) -> bool {
+    crate::userland::USER_PROCESS
+        .lock()
+        .can_write(pointer as usize, count.saturating_mul(size_of::<T>()))
+}
+
+/// The program must not make the kernel read outside of its readable memory
+fn is_readable<T>(pointer: *const T, count: usize) -> bool {
+    crate::userland::USER_PROCESS
+        .lock()
+        .can_read(pointer as usize, count.saturating_mul(size_of::<T>()))
+}
+
 fn syscall_write(buffer: *const u8, size: usize) -> u32 {
     use crate::peripherals::serial::SERIAL_PORT;
     use crate::peripherals::vga::TEXT_WRITER;
@@ -109,27 +151,233 @@ fn syscall_getkey() -> u32 {
         .unwrap_or(::core::u32::MAX)
 }
 
+fn syscall_keydropped() -> u32 {
+    crate::peripherals::keyboard::dropped_scans() as u32
+}
+
 fn syscall_gettick() -> u32 {
     use crate::peripherals::timer::uptime;
     uptime() as u32
 }
 
+fn syscall_time(t: *mut u32) -> u32 {
+    use crate::peripherals::timer::time;
+
+    if !t.is_null() && !is_writable(t, 1) {
+        return ::core::u32::MAX;
+    }
+
+    let (seconds, _) = time();
+    if !t.is_null() {
+        unsafe { *t = seconds as u32 };
+    }
+
+    seconds as u32
+}
+
+#[repr(C)]
+struct TimeVal {
+    seconds: u32,
+    microseconds: u32,
+}
+
+fn syscall_gettimeofday(tv: *mut TimeVal) -> u32 {
+    use crate::peripherals::timer::time;
+
+    if tv.is_null() || !is_writable(tv, 1) {
+        return ::core::u32::MAX;
+    }
+
+    let (seconds, nanoseconds) = time();
+    unsafe {
+        *tv = TimeVal {
+            seconds: seconds as u32,
+            microseconds: (nanoseconds / 1000) as u32,
+        }
+    };
+
+    0
+}
+
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
+
+#[repr(C)]
+struct TimeSpec {
+    seconds: u32,
+    nanoseconds: u32,
+}
+
+fn syscall_clock_gettime(clock: u32, tp: *mut TimeSpec) -> u32 {
+    use crate::peripherals::timer::{monotonic_ns, time};
+
+    if tp.is_null() || !is_writable(tp, 1) {
+        return ::core::u32::MAX;
+    }
+
+    let (seconds, nanoseconds) = match clock {
+        CLOCK_REALTIME => time(),
+        CLOCK_MONOTONIC => {
+            let monotonic = monotonic_ns();
+            (
+                (monotonic / 1_000_000_000) as usize,
+                (monotonic % 1_000_000_000) as usize,
+            )
+        }
+        _ => return ::core::u32::MAX,
+    };
+
+    unsafe {
+        *tp = TimeSpec {
+            seconds: seconds as u32,
+            nanoseconds: nanoseconds as u32,
+        }
+    };
+
+    0
+}
+
+/// There is a single process, so it is parked by halting until the deadline
+fn syscall_sleep(milliseconds: usize) -> u32 {
+    use crate::peripherals::timer::sleep;
+
+    sleep(milliseconds);
+
+    0
+}
+
 fn syscall_playsound(melody: *const speaker::Tone, repeat: bool) -> u32 {
-    speaker::start_melody_from(melody, repeat);
+    // The ROMs stop the sound with a null melody
+    if melody.is_null() {
+        speaker::stop();
+        return 0;
+    }
+
+    speaker::start_melody_from(melody, repeat)
+        .map(|_| 0)
+        .unwrap_or(::core::u32::MAX)
+}
+
+fn syscall_queuesound(melody: *const speaker::Tone, repeat: bool, priority: u32) -> u32 {
+    let priority = match priority {
+        0 => speaker::Priority::Music,
+        1 => speaker::Priority::Effect,
+        _ => return ::core::u32::MAX,
+    };
+
+    speaker::queue_melody_from(melody, repeat, priority)
+        .map(|_| 0)
+        .unwrap_or(::core::u32::MAX)
+}
+
+fn syscall_playfile(path: &str, repeat: bool) -> u32 {
+    speaker::play_file(path, repeat)
+        .map(|_| 0)
+        .unwrap_or(::core::u32::MAX)
+}
+
+const SOUND_STOP: u32 = 0;
+const SOUND_PAUSE: u32 = 1;
+const SOUND_RESUME: u32 = 2;
+
+fn syscall_soundctl(command: u32) -> u32 {
+    match command {
+        SOUND_STOP => speaker::stop(),
+        SOUND_PAUSE => speaker::pause(),
+        SOUND_RESUME => speaker::resume(),
+        _ => return ::core::u32::MAX,
+    }
+
+    0
+}
+
+#[repr(C)]
+struct SoundStatus {
+    state: u32,
+    priority: u32,
+    frequency: u32,
+    queued: u32,
+}
+
+fn syscall_soundstatus(status: *mut SoundStatus) -> u32 {
+    if status.is_null() || !is_writable(status, 1) {
+        return ::core::u32::MAX;
+    }
+
+    let s = speaker::status();
+    unsafe {
+        *status = SoundStatus {
+            state: s.state as u32,
+            priority: s.priority as u32,
+            frequency: s.frequency,
+            queued: s.queued as u32,
+        }
+    };
 
     0
 }
 
+#[repr(C)]
+struct PcmFormat {
+    rate: u32,
+    bits: u16,
+    channels: u16,
+}
+
+fn syscall_pcmsubmit(samples: *const u8, length: usize, format: *const PcmFormat) -> u32 {
+    if samples.is_null() || format.is_null() {
+        return ::core::u32::MAX;
+    }
+    if !is_readable(format, 1) || !is_readable(samples, length) {
+        return ::core::u32::MAX;
+    }
+
+    let format = unsafe { &*format };
+    if format.rate > ::core::u16::MAX as u32 || format.bits > 16 || format.channels > 2 {
+        return ::core::u32::MAX;
+    }
+
+    let format = sb16::Format {
+        rate: format.rate as u16,
+        bits: format.bits as u8,
+        channels: format.channels as u8,
+    };
+    let samples = unsafe { slice::from_raw_parts(samples, length) };
+
+    sb16::submit(samples, format)
+        .map(|length| length as u32)
+        .unwrap_or(::core::u32::MAX)
+}
+
+fn syscall_dmesg(buffer: *mut u8, size: usize) -> u32 {
+    if buffer.is_null() || !is_writable(buffer, size) {
+        return ::core::u32::MAX;
+    }
+
+    let buffer = unsafe { slice::from_raw_parts_mut(buffer, size) };
+    crate::logger::read_records(buffer) as u32
+}
+
+/// The kernel has nothing left to do once the user process exits
+fn syscall_exit(status: i32) -> ! {
+    info!("The user process exited with status {}", status);
+
+    #[cfg(test)]
+    crate::testing::exit_qemu(match status {
+        0 => crate::testing::ExitCode::Success,
+        _ => crate::testing::ExitCode::Failure,
+    });
+
+    #[cfg(not(test))]
+    crate::shutdown();
+}
+
 fn syscall_open(filename: &str, _flags: u32) -> u32 {
     use alloc::boxed::Box;
 
-    let fs = crate::kfs::get_fs();
-
-    let filename = if filename.starts_with('/') {
-        &filename[1..]
-    } else { filename };
+    let fs = crate::fs::get();
 
-    if let Some(inode) = fs.inodes().find(|i| i.filename() == filename) {
+    if let Some(inode) = fs.find(filename) {
         let reader = fs.reader(inode);
         crate::userland::USER_PROCESS.lock().store_file(Box::new(reader))
             .unwrap_or(::core::u32::MAX)
@@ -139,6 +387,10 @@ fn syscall_open(filename: &str, _flags: u32) -> u32 {
 }
 
 fn syscall_read(fd: u32, buffer: &mut [u8]) -> u32 {
+    if !is_writable(buffer.as_mut_ptr(), buffer.len()) {
+        return ::core::u32::MAX;
+    }
+
     let mut process = crate::userland::USER_PROCESS.lock();
 
     process.get_file(fd)
@@ -205,3 +457,21 @@ fn syscall_swapfrontbuffer(buffer: *const u8) -> u32 {
     vga::swap_frontbuffer(buffer).map(|_| 0)
         .unwrap_or(::core::u32::MAX)
 }
+
+fn syscall_getmouse(x: *mut i32, y: *mut i32, buttons: *mut i32) -> u32 {
+    if !is_writable(x, 1) || !is_writable(y, 1) || !is_writable(buttons, 1) {
+        return ::core::u32::MAX;
+    }
+
+    if let Some(event) = mouse::take_event() {
+        unsafe {
+            *x = event.dx;
+            *y = event.dy;
+            *buttons = event.buttons as i32;
+        }
+
+        0
+    } else {
+        ::core::u32::MAX
+    }
+}
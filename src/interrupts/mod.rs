@@ -1,24 +1,83 @@
+mod apic;
+mod exceptions;
+pub mod gdb;
 mod handlers;
+mod tables;
 
 use core::mem::size_of_val;
 use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
+use spin::Mutex;
 
 use super::memory::KERNEL_CODE_SEGMENT;
-use crate::arch::i386::instructions::idt::{lidt, IDTEntry, IDTR};
+use crate::arch::i386::instructions::idt::{lidt, HandlerFunc, IDTEntry, IDTR};
 use crate::arch::i386::instructions::lgdt::DPL;
-use crate::arch::i386::pic::PIC;
-use crate::arch::i386::pit::PIT;
+use crate::arch::i386::pic::{Pic, PIC};
+use crate::peripherals::timer::without_interrupts;
 
 // TODO Use #[naked] and llvm_asm!
 extern "C" {
     fn isr_0() -> !;
+    fn isr_1() -> !;
+    fn isr_3() -> !;
+    fn isr_4() -> !;
+    fn isr_5() -> !;
+    fn isr_6() -> !;
+    fn isr_7() -> !;
+    fn isr_8() -> !;
+    fn isr_9() -> !;
+    fn isr_10() -> !;
+    fn isr_11() -> !;
+    fn isr_12() -> !;
+    fn isr_13() -> !;
+    fn isr_14() -> !;
+    fn isr_16() -> !;
+    fn isr_17() -> !;
+    fn isr_18() -> !;
+    fn isr_19() -> !;
     fn isr_64() -> !;
     fn isr_65() -> !;
+    fn isr_66() -> !;
+    fn isr_67() -> !;
+    fn isr_68() -> !;
+    fn isr_69() -> !;
+    fn isr_70() -> !;
+    fn isr_71() -> !;
+    fn isr_72() -> !;
+    fn isr_79() -> !;
+    fn isr_80() -> !;
+    fn isr_81() -> !;
+    fn isr_82() -> !;
+    fn isr_83() -> !;
+    fn isr_84() -> !;
+    fn isr_85() -> !;
+    fn isr_86() -> !;
+    fn isr_87() -> !;
     fn isr_128() -> !;
 }
 
+/// Entry points of the CPU exceptions, by vector. The NMI and the reserved vector are unused.
+static EXCEPTION_ISRS: [Option<HandlerFunc>; exceptions::COUNT as usize] = [
+    Some(isr_0), Some(isr_1), None, Some(isr_3), Some(isr_4), Some(isr_5), Some(isr_6),
+    Some(isr_7), Some(isr_8), Some(isr_9), Some(isr_10), Some(isr_11), Some(isr_12),
+    Some(isr_13), Some(isr_14), None, Some(isr_16), Some(isr_17), Some(isr_18), Some(isr_19),
+];
+
+/// Entry points of the IRQs, by IRQ number
+static IRQ_ISRS: [HandlerFunc; Pic::IRQ_COUNT as usize] = [
+    isr_64, isr_65, isr_66, isr_67, isr_68, isr_69, isr_70, isr_71, isr_80, isr_81, isr_82,
+    isr_83, isr_84, isr_85, isr_86, isr_87,
+];
+
+static IRQ_HANDLERS: Mutex<[Option<fn()>; Pic::IRQ_COUNT as usize]> =
+    Mutex::new([None; Pic::IRQ_COUNT as usize]);
+
+static LOCAL_TIMER_HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+
+static SPURIOUS_IRQS: AtomicUsize = AtomicUsize::new(0);
+
 #[repr(C, packed)]
 #[derive(Clone, Copy, Debug)]
 pub struct InterruptContext {
@@ -36,39 +95,157 @@ pub struct InterruptContext {
     eax: u32,
     interrupt_number: u32,
     error_code: u32,
+    // Pushed by the CPU
+    eip: u32,
+    cs: u32,
+    eflags: u32,
 }
 
 #[no_mangle]
 #[allow(safe_packed_borrows)]
 pub extern "C" fn isr_generic_handler(context: &mut InterruptContext) {
     match context.interrupt_number {
-        64 => handlers::pit_handler(context),
-        65 => handlers::keyboard_handler(context),
+        n if n < exceptions::COUNT => exceptions::exception_handler(context),
         128 => handlers::syscall_handler(context),
-        _ => (),
+        n if n == apic::TIMER_VECTOR as u32 => local_timer_handler(),
+        // Spurious interrupts of the local APIC must not be acknowledged
+        n if n == apic::SPURIOUS_VECTOR as u32 => {
+            SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+        }
+        n => {
+            if let Some(irq) = Pic::irq(n as u8) {
+                irq_handler(irq);
+            }
+        }
+    }
+}
+
+fn irq_handler(irq: u8) {
+    let use_apic = apic::is_enabled();
+    if !use_apic && PIC.lock().is_spurious(irq) {
+        SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+        return;
     }
+
+    // Copy the handler, so it can register or unregister IRQs
+    let handler = IRQ_HANDLERS.lock()[irq as usize];
+    match handler {
+        Some(handler) => handler(),
+        // The I/O APIC only delivers registered IRQs. It is still acknowledged, as only the
+        // spurious vector of the local APIC must not be.
+        None if use_apic => {
+            SPURIOUS_IRQS.fetch_add(1, Ordering::Relaxed);
+        }
+        None => (),
+    }
+
+    if use_apic {
+        apic::end_of_interrupt();
+    } else {
+        PIC.lock().end_of_interrupt(irq);
+    }
+}
+
+fn local_timer_handler() {
+    let handler = *LOCAL_TIMER_HANDLER.lock();
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    apic::end_of_interrupt();
+}
+
+/// Call the handler periodically with the local APIC timer.
+/// Returns an error if the APIC is not used, so the PIT must be used instead.
+pub fn start_local_timer(rate: usize, handler: fn()) -> Result<(), ()> {
+    without_interrupts(|| {
+        LOCAL_TIMER_HANDLER.lock().replace(handler);
+        apic::start_timer(rate).map_err(|_| {
+            LOCAL_TIMER_HANDLER.lock().take();
+        })
+    })
+}
+
+/// Call the handler on each IRQ, and unmask the line.
+/// Returns an error if the line doesn't exist or already has a handler.
+pub fn register_irq(irq: u8, handler: fn()) -> Result<(), ()> {
+    if irq >= Pic::IRQ_COUNT || irq == Pic::CASCADE_IRQ {
+        return Err(());
+    }
+
+    without_interrupts(|| {
+        let mut handlers = IRQ_HANDLERS.lock();
+        if handlers[irq as usize].is_some() {
+            return Err(());
+        }
+
+        handlers[irq as usize] = Some(handler);
+        if apic::is_enabled() {
+            apic::unmask(irq);
+        } else {
+            PIC.lock().unmask(irq);
+        }
+        Ok(())
+    })
+}
+
+/// Mask the line and forget its handler
+pub fn unregister_irq(irq: u8) {
+    if irq >= Pic::IRQ_COUNT || irq == Pic::CASCADE_IRQ {
+        return;
+    }
+
+    without_interrupts(|| {
+        if apic::is_enabled() {
+            apic::mask(irq);
+        } else {
+            PIC.lock().mask(irq);
+        }
+        IRQ_HANDLERS.lock()[irq as usize] = None;
+    });
+}
+
+/// Number of interrupts which were not raised by a device: IRQs 7 and 15 of the 8259,
+/// and spurious interrupts of the APIC
+pub fn spurious_irqs() -> usize {
+    SPURIOUS_IRQS.load(Ordering::Relaxed)
 }
 
 lazy_static! {
     static ref IDT: [IDTEntry; 255] = {
         let mut idt = [IDTEntry(0); 255];
 
-        idt[0] = IDTEntry::new_interrupt_gate(isr_0, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
-        idt[64] = IDTEntry::new_interrupt_gate(isr_64, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
-        idt[65] = IDTEntry::new_interrupt_gate(isr_65, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
+        for (vector, isr) in EXCEPTION_ISRS.iter().enumerate() {
+            if let Some(isr) = *isr {
+                idt[vector] =
+                    IDTEntry::new_interrupt_gate(isr, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
+            }
+        }
+        // Breakpoints of the debugger can be in the user process
+        idt[3] = IDTEntry::new_interrupt_gate(isr_3, KERNEL_CODE_SEGMENT as u16, DPL::Ring3);
+        idt[apic::TIMER_VECTOR as usize] =
+            IDTEntry::new_interrupt_gate(isr_72, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
+        idt[apic::SPURIOUS_VECTOR as usize] =
+            IDTEntry::new_interrupt_gate(isr_79, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
+        for (irq, &isr) in IRQ_ISRS.iter().enumerate() {
+            idt[Pic::interrupt(irq as u8) as usize] =
+                IDTEntry::new_interrupt_gate(isr, KERNEL_CODE_SEGMENT as u16, DPL::Ring0);
+        }
         idt[128] = IDTEntry::new_interrupt_gate(isr_128, KERNEL_CODE_SEGMENT as u16, DPL::Ring3);
 
         idt
     };
 }
 
-pub fn init() {
+/// Load the IDT and mask every IRQ, then switch to the APIC if asked and available.
+/// Devices register their IRQ before `enable` is called.
+pub fn init(use_apic: bool) {
     load_idt();
 
     PIC.lock().init();
-    PIT.lock().set_rate_generator(100);
-
-    enable();
+    if use_apic {
+        apic::init();
+    }
 }
 
 fn load_idt() {
@@ -80,6 +257,6 @@ fn load_idt() {
     lidt(&idtr);
 }
 
-fn enable() {
+pub fn enable() {
     unsafe { llvm_asm!("sti" :::: "volatile") }
 }
@@ -0,0 +1,251 @@
+//! Discovery of the APICs through the ACPI MADT, or the older MultiProcessor tables
+//!
+//! Memory is identity mapped, so the tables are read where the firmware left them.
+
+use core::mem::size_of;
+use core::slice;
+
+use crate::arch::i386::apic::{Polarity, Trigger};
+use crate::arch::i386::pic::Pic;
+
+/// Segment of the Extended BIOS Data Area, stored in the BIOS Data Area
+const EBDA_SEGMENT_POINTER: usize = 0x40E;
+const BIOS_AREA: (usize, usize) = (0xE0000, 0x100000);
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_SOURCE_OVERRIDE: u8 = 2;
+
+const MP_SIGNATURE: &[u8; 4] = b"_MP_";
+const MP_CONFIG_SIGNATURE: &[u8; 4] = b"PCMP";
+/// The IMCR routes the 8259 straight to the CPU, and must be switched to the APIC
+const MP_FEATURE_IMCR: u8 = 1 << 7;
+const MP_PROCESSOR: u8 = 0;
+const MP_BUS: u8 = 1;
+const MP_IO_APIC: u8 = 2;
+const MP_IO_INTERRUPT: u8 = 3;
+const MP_IO_APIC_ENABLED: u8 = 1 << 0;
+const MP_INTERRUPT_INT: u8 = 0;
+const MP_BUS_ISA: &[u8; 6] = b"ISA   ";
+
+/// Polarity and trigger flags, shared by the MADT and the MP tables
+const FLAGS_POLARITY_MASK: u16 = 0b11;
+const FLAGS_POLARITY_LOW: u16 = 0b11;
+const FLAGS_TRIGGER_MASK: u16 = 0b1100;
+const FLAGS_TRIGGER_LEVEL: u16 = 0b1100;
+
+/// Input of the I/O APIC wired to an ISA IRQ
+#[derive(Clone, Copy, Debug)]
+pub struct Route {
+    pub input: u8,
+    pub polarity: Polarity,
+    pub trigger: Trigger,
+}
+
+impl Route {
+    /// ISA IRQs are wired to the input of the same number unless overridden
+    const fn identity(irq: u8) -> Self {
+        Route {
+            input: irq,
+            polarity: Polarity::ActiveHigh,
+            trigger: Trigger::Edge,
+        }
+    }
+
+    fn with_flags(input: u8, flags: u16) -> Self {
+        Route {
+            input,
+            polarity: if flags & FLAGS_POLARITY_MASK == FLAGS_POLARITY_LOW {
+                Polarity::ActiveLow
+            } else {
+                Polarity::ActiveHigh
+            },
+            trigger: if flags & FLAGS_TRIGGER_MASK == FLAGS_TRIGGER_LEVEL {
+                Trigger::Level
+            } else {
+                Trigger::Edge
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Topology {
+    pub local_apic: u32,
+    /// Address of the I/O APIC receiving the ISA IRQs
+    pub io_apic: u32,
+    pub routes: [Route; Pic::IRQ_COUNT as usize],
+    /// The IMCR must be set to disconnect the 8259
+    pub has_imcr: bool,
+}
+
+impl Topology {
+    fn new(local_apic: u32) -> Self {
+        let mut routes = [Route::identity(0); Pic::IRQ_COUNT as usize];
+        for (irq, route) in routes.iter_mut().enumerate() {
+            *route = Route::identity(irq as u8);
+        }
+
+        Topology {
+            local_apic,
+            io_apic: 0,
+            routes,
+            has_imcr: false,
+        }
+    }
+}
+
+/// Find the APICs, from the ACPI tables or else from the MP tables
+pub fn find() -> Option<Topology> {
+    unsafe { find_madt().or_else(|| find_mp()) }
+}
+
+/// Address of the first structure with the signature in the EBDA or the BIOS area
+unsafe fn search(signature: &[u8], length: usize) -> Option<usize> {
+    let ebda = (*(EBDA_SEGMENT_POINTER as *const u16) as usize) << 4;
+    let areas = [(ebda, ebda + 1024), BIOS_AREA];
+
+    for &(start, end) in areas.iter().filter(|&&(start, _)| start != 0) {
+        // Both structures are aligned on 16 bytes
+        for address in (start..end - length).step_by(16) {
+            let bytes = slice::from_raw_parts(address as *const u8, length);
+            if bytes.starts_with(signature) && checksum(bytes) {
+                return Some(address);
+            }
+        }
+    }
+
+    None
+}
+
+/// The bytes of ACPI and MP structures sum to 0
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+unsafe fn read<T: Copy>(address: usize) -> T {
+    (address as *const T).read_unaligned()
+}
+
+unsafe fn find_madt() -> Option<Topology> {
+    // Only the ACPI 1.0 part is needed, the RSDT is always there
+    let rsdp = search(RSDP_SIGNATURE, 20)?;
+    let rsdt = read::<u32>(rsdp + 16) as usize;
+    let rsdt_length = read::<u32>(rsdt + 4) as usize;
+    if !checksum(slice::from_raw_parts(rsdt as *const u8, rsdt_length)) {
+        return None;
+    }
+
+    // The table header is 36 bytes long, followed by pointers to the tables
+    let madt = (rsdt + 36..rsdt + rsdt_length)
+        .step_by(size_of::<u32>())
+        .map(|entry| read::<u32>(entry) as usize)
+        .find(|&table| slice::from_raw_parts(table as *const u8, 4) == MADT_SIGNATURE)?;
+    let madt_length = read::<u32>(madt + 4) as usize;
+    if !checksum(slice::from_raw_parts(madt as *const u8, madt_length)) {
+        return None;
+    }
+
+    let mut topology = Topology::new(read::<u32>(madt + 36));
+    let flags = read::<u32>(madt + 40);
+    debug!("ACPI MADT at 0x{:X}, 8259 present: {}", madt, flags & MADT_PCAT_COMPAT != 0);
+
+    let mut entry = madt + 44;
+    while entry + 2 <= madt + madt_length {
+        let (typ, length) = (read::<u8>(entry), read::<u8>(entry + 1) as usize);
+        if length < 2 {
+            break;
+        }
+
+        match typ {
+            // The ISA IRQs go to the I/O APIC whose inputs start at 0
+            MADT_IO_APIC if read::<u32>(entry + 8) == 0 => {
+                topology.io_apic = read::<u32>(entry + 4);
+            }
+            MADT_SOURCE_OVERRIDE if read::<u8>(entry + 2) == 0 => {
+                let irq = read::<u8>(entry + 3);
+                let input = read::<u32>(entry + 4);
+                let flags = read::<u16>(entry + 8);
+                if irq < Pic::IRQ_COUNT && input < 0x100 {
+                    topology.routes[irq as usize] = Route::with_flags(input as u8, flags);
+                }
+            }
+            _ => (),
+        }
+
+        entry += length;
+    }
+
+    if topology.io_apic == 0 {
+        None
+    } else {
+        Some(topology)
+    }
+}
+
+unsafe fn find_mp() -> Option<Topology> {
+    let floating = search(MP_SIGNATURE, 16)?;
+    let features = read::<u8>(floating + 12);
+    let config = read::<u32>(floating + 4) as usize;
+    // Without configuration table, the system has one of the default configurations
+    if config == 0 {
+        return None;
+    }
+
+    let length = read::<u16>(config + 4) as usize;
+    let bytes = slice::from_raw_parts(config as *const u8, length);
+    if !bytes.starts_with(MP_CONFIG_SIGNATURE) || !checksum(bytes) {
+        return None;
+    }
+
+    let mut topology = Topology::new(read::<u32>(config + 36));
+    topology.has_imcr = features & MP_FEATURE_IMCR != 0;
+    debug!("MP configuration table at 0x{:X}", config);
+
+    let count = read::<u16>(config + 34);
+    let mut isa_bus = None;
+    let mut io_apic_id = None;
+
+    // Buses and I/O APICs come before the interrupts
+    let mut entry = config + 44;
+    for _ in 0..count {
+        match read::<u8>(entry) {
+            MP_PROCESSOR => entry += 20,
+            MP_BUS => {
+                if slice::from_raw_parts((entry + 2) as *const u8, 6) == MP_BUS_ISA {
+                    isa_bus = Some(read::<u8>(entry + 1));
+                }
+                entry += 8;
+            }
+            MP_IO_APIC => {
+                if io_apic_id.is_none() && read::<u8>(entry + 3) & MP_IO_APIC_ENABLED != 0 {
+                    io_apic_id = Some(read::<u8>(entry + 1));
+                    topology.io_apic = read::<u32>(entry + 4);
+                }
+                entry += 8;
+            }
+            MP_IO_INTERRUPT => {
+                let irq = read::<u8>(entry + 5);
+                if read::<u8>(entry + 1) == MP_INTERRUPT_INT
+                    && Some(read::<u8>(entry + 4)) == isa_bus
+                    && Some(read::<u8>(entry + 6)) == io_apic_id
+                    && irq < Pic::IRQ_COUNT
+                {
+                    let flags = read::<u16>(entry + 2);
+                    topology.routes[irq as usize] = Route::with_flags(read::<u8>(entry + 7), flags);
+                }
+                entry += 8;
+            }
+            // Local interrupts, and unknown entries which are all 8 bytes long
+            _ => entry += 8,
+        }
+    }
+
+    if topology.io_apic == 0 {
+        None
+    } else {
+        Some(topology)
+    }
+}
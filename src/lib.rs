@@ -9,11 +9,16 @@
 #![feature(lang_items)]
 #![feature(panic_info_message)]
 #![no_std]
+#![cfg_attr(test, no_main)]
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(crate::testing::runner))]
+#![cfg_attr(test, reexport_test_harness_main = "test_main")]
 
 extern crate alloc;
 extern crate bitfield;
 extern crate elf;
 extern crate kallocator;
+extern crate kfs;
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
@@ -24,14 +29,18 @@ extern crate vga;
 extern crate volatile; // TODO Move
 
 mod arch;
+mod backtrace;
+mod cmdline;
+mod fs;
 mod interrupts;
-mod kfs;
 mod logger;
 mod memory;
 mod multiboot;
 mod peripherals;
 mod startup;
 mod strings;
+#[cfg(test)]
+mod testing;
 mod userland;
 
 use core::fmt::Write;
@@ -57,24 +66,21 @@ pub extern "C" fn k_main(magic: u32, infos: &'static multiboot::MultibootInfo) -
 
     startup::startup(infos);
 
-    // Load the first module and execute it
-    if let Some(module) = infos.mods().next() {
-        load_and_execute_module(infos, &module);
-    } else {
-        warn!("No module detected");
-    }
+    #[cfg(test)]
+    test_main();
 
-    info!("Shutdown");
-    loop {
-        unsafe { llvm_asm!("hlt\n\t" :::: "volatile") }
+    // Execute the program from the file system loaded at startup
+    if let Some(fs) = fs::try_get() {
+        execute_program(fs);
     }
+
+    shutdown();
 }
 
-fn load_and_execute_module(infos: &multiboot::MultibootInfo, m: &'static multiboot::MultibootMod) {
-    // TODO Refactor
+fn execute_program(fs: kfs::Kfs<'static>) {
     // Extract the executable name from the mutliboot command line.
-    let executable = match infos.cmdline() {
-        Some(cmdline) if cmdline.starts_with('/') => &cmdline[1..],
+    let executable = match cmdline::executable() {
+        Some(executable) if executable.starts_with('/') => &executable[1..],
         Some(_) => {
             warn!("The command line argument doesn't start with a '/'");
             return;
@@ -85,17 +91,43 @@ fn load_and_execute_module(infos: &multiboot::MultibootInfo, m: &'static multibo
         }
     };
 
-    unsafe { kfs::init(m.mod_start, m.mod_end).expect("kfs::init failed") };
-    let fs = kfs::get_fs();
+    if cmdline::arguments().next().is_some() {
+        warn!("The arguments of the executable are ignored");
+    }
 
     if let Some(inode) = fs.inodes().find(|i| i.filename() == executable) {
         let reader = fs.reader(inode);
-        userland::execute_file(reader);
+        if let Err(e) = userland::execute_file(reader) {
+            warn!("Could not execute \"{}\": {:?}", executable, e);
+        }
     } else {
         warn!("Executable \"{}\" not found in module.", executable);
     };
 }
 
+/// The test ROM doesn't return, it ends with the exit syscall
+#[cfg(test)]
+fn shutdown() -> ! {
+    testing::exit_qemu(match cmdline::executable() {
+        Some(_) => testing::ExitCode::Failure,
+        None => testing::ExitCode::Success,
+    });
+}
+
+#[cfg(not(test))]
+fn shutdown() -> ! {
+    info!("Shutdown");
+    loop {
+        unsafe { llvm_asm!("hlt\n\t" :::: "volatile") }
+    }
+}
+
+#[cfg(test)]
+fn abort() -> ! {
+    testing::exit_qemu(testing::ExitCode::Failure);
+}
+
+#[cfg(not(test))]
 fn abort() -> ! {
     loop {
         unsafe { llvm_asm!("hlt\n\t" :::: "volatile") };
@@ -113,6 +145,13 @@ fn alloc_error_handler(layout: ::core::alloc::Layout) -> ! {
 #[lang = "eh_personality"]
 extern "C" fn eh_personality() {}
 
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panic(info);
+}
+
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     if let Some(location) = info.location() {
@@ -140,5 +179,8 @@ fn panic(info: &PanicInfo) -> ! {
         write_serial!(", \"{}\"", s);
         write_vga!(", \"{}\"", s);
     }
+    write_serial!("\n");
+    write_vga!("\n");
+    backtrace::print_kernel();
     abort();
 }
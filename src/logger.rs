@@ -1,31 +1,240 @@
+//! Kernel logger: records go to the serial port, and the last ones are kept in memory
+//! to be read with the dmesg syscall
+//!
+//! Levels can be set per target with `log=fs:trace,peripherals::mouse:off,info`: the
+//! most specific target wins, and a level alone applies to the other targets.
+
+use core::fmt::{self, Write};
+
 use log::{LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+
+use crate::peripherals::serial::SERIAL_PORT;
+use crate::peripherals::timer::{self, without_interrupts};
+use crate::write_serial;
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+const MAX_DIRECTIVES: usize = 8;
+/// Size of the ring buffer of records
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Targets are the module paths, which start with the name of the crate
+const CRATE_PREFIX: &str = concat!(env!("CARGO_PKG_NAME"), "::");
+
+static LOGGER: KernelLogger = KernelLogger;
 
-// To import write_serial!
-use crate::*;
+static LEVELS: Mutex<Levels> = Mutex::new(Levels {
+    default: DEFAULT_LEVEL,
+    directives: [None; MAX_DIRECTIVES],
+});
 
-static LOGGER: SerialLogger = SerialLogger;
+static BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer {
+    data: [0; BUFFER_SIZE],
+    start: 0,
+    length: 0,
+    wrapped: false,
+});
 
-struct SerialLogger;
+struct KernelLogger;
 
-impl Log for SerialLogger {
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let level = without_interrupts(|| LEVELS.lock().level(metadata.target()));
+        metadata.level() <= level
     }
 
     fn log(&self, record: &Record) {
-        write_serial!(
-            "[{level:<5}] [{target}] {args}\n",
-            level = record.level(),
-            target = record.target(),
-            args = record.args()
-        );
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let uptime = timer::uptime();
+
+        // Records can come from interrupt handlers
+        without_interrupts(|| {
+            write_serial!(
+                "[{level:<5}] [{target}] {args}\n",
+                level = record.level(),
+                target = record.target(),
+                args = record.args()
+            );
+
+            write!(
+                BUFFER.lock(),
+                "[{seconds:5}.{milliseconds:03}] [{level:<5}] [{target}] {args}\n",
+                seconds = uptime / 1000,
+                milliseconds = uptime % 1000,
+                level = record.level(),
+                target = record.target(),
+                args = record.args()
+            )
+            .ok();
+        });
     }
 
     fn flush(&self) {}
 }
 
+struct Levels {
+    default: LevelFilter,
+    /// Targets, without the name of the crate, and their level
+    directives: [Option<(&'static str, LevelFilter)>; MAX_DIRECTIVES],
+}
+
+impl Levels {
+    fn level(&self, target: &str) -> LevelFilter {
+        let target = if target.starts_with(CRATE_PREFIX) {
+            &target[CRATE_PREFIX.len()..]
+        } else {
+            target
+        };
+
+        self.directives
+            .iter()
+            .flatten()
+            .filter(|(prefix, _)| {
+                target.starts_with(prefix)
+                    && (target.len() == prefix.len() || target[prefix.len()..].starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.default)
+    }
+
+    /// The most verbose level, under which records may be enabled
+    fn max(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .flatten()
+            .map(|&(_, level)| level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+/// Records, as text lines. The oldest are overwritten when it is full.
+struct RingBuffer {
+    data: [u8; BUFFER_SIZE],
+    start: usize,
+    length: usize,
+    /// Bytes were overwritten, so the first line may be incomplete
+    wrapped: bool,
+}
+
+impl RingBuffer {
+    fn push(&mut self, byte: u8) {
+        if self.length == BUFFER_SIZE {
+            self.data[self.start] = byte;
+            self.start = (self.start + 1) % BUFFER_SIZE;
+            self.wrapped = true;
+        } else {
+            self.data[(self.start + self.length) % BUFFER_SIZE] = byte;
+            self.length += 1;
+        }
+    }
+
+    fn byte(&self, index: usize) -> u8 {
+        self.data[(self.start + index) % BUFFER_SIZE]
+    }
+
+    /// Copy the most recent whole lines which fit in the buffer, and returns their size
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        let mut first = self.length.saturating_sub(buffer.len());
+        if first != 0 || self.wrapped {
+            // Skip up to the start of the next line, unless the skipped part ends one
+            let starts_line = first != 0 && self.byte(first - 1) == b'\n';
+            if !starts_line {
+                first = (first..self.length)
+                    .find(|&i| self.byte(i) == b'\n')
+                    .map(|i| i + 1)
+                    .unwrap_or(self.length);
+            }
+        }
+
+        let length = self.length - first;
+        for (i, byte) in buffer[..length].iter_mut().enumerate() {
+            *byte = self.byte(first + i);
+        }
+
+        length
+    }
+}
+
+impl fmt::Write for RingBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+
+        Ok(())
+    }
+}
+
 pub fn init() {
     log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(LevelFilter::Debug))
+        .map(|()| log::set_max_level(DEFAULT_LEVEL))
         .expect("Initialize logger");
 }
+
+/// Set the levels from a comma separated list of `target:level` directives, or of levels
+/// applying to the other targets
+pub fn set_levels(spec: &'static str) {
+    let mut levels = Levels {
+        default: DEFAULT_LEVEL,
+        directives: [None; MAX_DIRECTIVES],
+    };
+    let mut count = 0;
+
+    for directive in spec.split(',').filter(|d| !d.is_empty()) {
+        let mut split = directive.rsplitn(2, ':');
+        let level = split.next().and_then(|level| level.parse::<LevelFilter>().ok());
+        let target = split.next();
+
+        match (target, level) {
+            (None, Some(level)) => levels.default = level,
+            (Some(target), Some(level)) if count < MAX_DIRECTIVES => {
+                levels.directives[count] = Some((target, level));
+                count += 1;
+            }
+            (Some(target), Some(_)) => warn!("Too many log directives, ignoring {}", target),
+            (_, None) => warn!("Invalid log directive \"{}\"", directive),
+        }
+    }
+
+    let max = levels.max();
+    without_interrupts(|| *LEVELS.lock() = levels);
+    log::set_max_level(max);
+}
+
+/// Copy the most recent records which fit in the buffer, and returns their size
+pub fn read_records(buffer: &mut [u8]) -> usize {
+    without_interrupts(|| BUFFER.lock().read(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::alloc::{alloc_zeroed, Layout};
+    use alloc::boxed::Box;
+    use alloc::format;
+
+    use super::*;
+
+    #[test_case]
+    fn ring_buffer_keeps_whole_lines() {
+        // Too large for the stack, and empty once zeroed
+        let pointer = unsafe { alloc_zeroed(Layout::new::<RingBuffer>()) } as *mut RingBuffer;
+        assert!(!pointer.is_null(), "No memory for the ring buffer");
+        let mut buffer = unsafe { Box::from_raw(pointer) };
+
+        // One line more than the buffer holds
+        let lines = BUFFER_SIZE / 8 + 1;
+        for i in 0..lines {
+            write!(buffer, "{:07}\n", i).unwrap();
+        }
+        assert!(buffer.wrapped);
+
+        let mut records = [0; 20];
+        let length = buffer.read(&mut records);
+        let expected = format!("{:07}\n{:07}\n", lines - 2, lines - 1);
+        assert_eq!(&records[..length], expected.as_bytes());
+    }
+}
@@ -2,8 +2,21 @@
 
 mod qwerty;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use spin::Mutex;
 
+use super::mouse;
+use super::timer::{self, TimerId};
+
+use crate::arch::i386::instructions::Port;
+use crate::interrupts;
+
+const IRQ: u8 = 1;
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 0b00000001;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Key {
     Esc,
@@ -90,61 +103,422 @@ impl Modifiers {
 
 static MODIFIERS: Mutex<Modifiers> = Mutex::new(Modifiers::new());
 
-/// Circular buffer for scan codes
-pub struct ScanBuffer {
-    read: u8,
-    write: u8,
-    overlap: bool,
-    buffer: [u8; ScanBuffer::SIZE],
+/// Default size of the buffer read by the `getkey` syscall
+pub const DEFAULT_BUFFER_SIZE: usize = 128;
+/// Bounds of the size of the `getkey` buffer, which holds at most one extended scan
+const MIN_BUFFER_SIZE: usize = 4;
+const MAX_BUFFER_SIZE: usize = 1024;
+
+/// Delay before a held key repeats, in milliseconds
+const REPEAT_DELAY: usize = 500;
+/// Interval between two repeats, in milliseconds
+const REPEAT_PERIOD: usize = 33;
+
+/// Sent before the scan of the keys added after the XT keyboard
+const SCAN_EXTENDED_PREFIX: u8 = 0xE0;
+
+/// Scan, with its extended prefix if any, so that both are kept or dropped together
+#[derive(Clone, Copy)]
+struct ScanEvent {
+    scan: u8,
+    extended: bool,
+    /// The scan is a typematic repeat of a key already held down
+    repeat: bool,
 }
 
-impl ScanBuffer {
-    const SIZE: usize = 16;
+impl ScanEvent {
+    const EMPTY: ScanEvent = ScanEvent {
+        scan: 0,
+        extended: false,
+        repeat: false,
+    };
+}
+
+/// Index of a key in the held keys, the extended ones are after the others
+fn key_index(scan: u8, extended: bool) -> usize {
+    (extended as usize) << 7 | (scan & 0b01111111) as usize
+}
 
+/// Circular buffer for scan codes, sized by each consumer.
+///
+/// When the buffer is under pressure, typematic repeats are dropped first, so
+/// release events are never lost in favor of a key that is already held down.
+pub struct ScanBuffer<const SIZE: usize> {
+    /// Number of scans used in the storage, up to `SIZE`
+    capacity: usize,
+    read: usize,
+    len: usize,
+    /// Number of scans dropped since the creation of the buffer
+    dropped: usize,
+    /// Keys currently held down, as seen by the writer
+    pressed: [bool; 256],
+    /// The prefix of the extended scan at the read position was already read
+    prefix_read: bool,
+    buffer: [ScanEvent; SIZE],
+}
+
+impl<const SIZE: usize> ScanBuffer<SIZE> {
     pub const fn new() -> Self {
         ScanBuffer {
+            capacity: SIZE,
             read: 0,
-            write: 0,
-            overlap: false,
-            buffer: [0; ScanBuffer::SIZE],
+            len: 0,
+            dropped: 0,
+            pressed: [false; 256],
+            prefix_read: false,
+            buffer: [ScanEvent::EMPTY; SIZE],
         }
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Use `capacity` scans of the storage, the scans in the buffer are dropped
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0 && capacity <= SIZE);
+
+        self.dropped += self.len;
+        self.capacity = capacity;
+        self.read = 0;
+        self.len = 0;
+        self.prefix_read = false;
+    }
+
     pub fn is_full(&self) -> bool {
-        self.overlap
+        self.len == self.capacity
     }
 
     pub fn is_empty(&self) -> bool {
-        !self.overlap && self.read == self.write
+        self.len == 0
     }
 
-    // TODO Only write when pressed ?
-    /// Write the scan into the buffer, or return false if the buffer is full
-    pub fn write(&mut self, scan: u8) -> bool {
-        if self.is_full() {
-            false
-        } else {
-            self.buffer[self.write as usize] = scan;
-            self.write = (self.write + 1) % Self::SIZE as u8;
-            self.overlap = self.write == self.read;
-            true
+    /// Past three quarters of the capacity, typematic repeats are dropped.
+    fn is_under_pressure(&self) -> bool {
+        self.len >= self.capacity - self.capacity / 4
+    }
+
+    /// Number of scans that were dropped because the buffer was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Returns true if the scan is a press of a key already held down
+    pub fn is_held(&self, scan: u8, extended: bool) -> bool {
+        (scan & 0b10000000) == 0 && self.pressed[key_index(scan, extended)]
+    }
+
+    /// Write the scan into the buffer, preceded by the extended prefix if `extended`, or
+    /// return false if it was dropped
+    ///
+    /// The held keys only follow the stored scans, as the reader never sees the others.
+    pub fn write(&mut self, scan: u8, extended: bool) -> bool {
+        let is_pressed = (scan & 0b10000000) == 0;
+        let key = key_index(scan, extended);
+        let repeat = is_pressed && self.pressed[key];
+
+        if repeat && self.is_under_pressure() {
+            self.dropped += 1;
+            return false;
+        }
+
+        // Only a release may take the place of a repeat
+        if self.is_full() && (is_pressed || !self.evict_repeat()) {
+            self.dropped += 1;
+            return false;
         }
+
+        self.buffer[(self.read + self.len) % self.capacity] = ScanEvent {
+            scan,
+            extended,
+            repeat,
+        };
+        self.len += 1;
+        self.pressed[key] = is_pressed;
+        true
     }
 
+    /// Read the next byte, which is the prefix of an extended scan before the scan itself
     pub fn read(&mut self) -> Option<u8> {
         if self.is_empty() {
-            None
+            return None;
+        }
+
+        let event = self.buffer[self.read];
+        if event.extended && !self.prefix_read {
+            self.prefix_read = true;
+            return Some(SCAN_EXTENDED_PREFIX);
+        }
+
+        self.prefix_read = false;
+        self.read = (self.read + 1) % self.capacity;
+        self.len -= 1;
+        Some(event.scan)
+    }
+
+    /// Remove the most recent typematic repeat, or return false if there is none
+    fn evict_repeat(&mut self) -> bool {
+        // The scan whose prefix was read must follow it
+        let position = (0..self.len)
+            .rev()
+            .filter(|&i| i != 0 || !self.prefix_read)
+            .find(|i| self.buffer[(self.read + i) % self.capacity].repeat);
+
+        if let Some(position) = position {
+            for i in position..self.len - 1 {
+                self.buffer[(self.read + i) % self.capacity] =
+                    self.buffer[(self.read + i + 1) % self.capacity];
+            }
+            self.len -= 1;
+            self.dropped += 1;
+            true
         } else {
-            let scan = self.buffer[self.read as usize];
-            self.read = (self.read + 1) % Self::SIZE as u8;
-            Some(scan)
+            false
         }
     }
 }
 
-pub static BUFFER: Mutex<ScanBuffer> = Mutex::new(ScanBuffer::new());
+pub static BUFFER: Mutex<ScanBuffer<MAX_BUFFER_SIZE>> = Mutex::new(ScanBuffer::new());
+
+/// Key repeated by the repeat timer
+struct Repeat {
+    extended: bool,
+    scan: u8,
+    timer: TimerId,
+}
+
+static REPEAT: Mutex<Option<Repeat>> = Mutex::new(None);
+/// The last scan was the extended prefix, which is written with the next scan
+static PENDING_PREFIX: AtomicBool = AtomicBool::new(false);
+
+pub fn init(buffer_size: usize) {
+    let buffer_size = if buffer_size < MIN_BUFFER_SIZE || buffer_size > MAX_BUFFER_SIZE {
+        warn!(
+            "Keyboard buffer of {} scans out of [{}, {}], using {}",
+            buffer_size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE, DEFAULT_BUFFER_SIZE
+        );
+        DEFAULT_BUFFER_SIZE
+    } else {
+        buffer_size
+    };
+    BUFFER.lock().set_capacity(buffer_size);
+
+    interrupts::register_irq(IRQ, on_interrupt).expect("The keyboard IRQ is free");
+}
+
+fn on_interrupt() {
+    let status = unsafe { Port::<u8>::new(STATUS_PORT).read() };
+    // The mouse shares the controller
+    if status & STATUS_OUTPUT_FULL != 0 && !mouse::has_aux_data(status) {
+        let scan = unsafe { Port::new(DATA_PORT).read() };
+        receive_scan(scan);
+    }
+}
 
 pub fn receive_scan(scan: u8) {
-    let mut buffer = BUFFER.lock();
-    buffer.write(scan);
+    if scan == SCAN_EXTENDED_PREFIX {
+        PENDING_PREFIX.store(true, Ordering::Release);
+        return;
+    }
+    let extended = PENDING_PREFIX.swap(false, Ordering::AcqRel);
+    let is_pressed = (scan & 0b10000000) == 0;
+
+    // The repeat timer replaces the typematic repeats of the keyboard
+    if is_pressed && is_repeating(scan, extended) {
+        return;
+    }
+
+    let stored = BUFFER.lock().write(scan, extended);
+    update_repeat(scan, extended, stored);
+}
+
+/// Returns true if the key is the one repeated by the repeat timer
+fn is_repeating(scan: u8, extended: bool) -> bool {
+    match *REPEAT.lock() {
+        Some(ref r) => r.scan == scan && r.extended == extended,
+        None => false,
+    }
+}
+
+/// Repeat the last pressed key until it is released, if its press was `stored`
+fn update_repeat(scan: u8, extended: bool, stored: bool) {
+    let is_pressed = (scan & 0b10000000) == 0;
+    let key = scan & 0b01111111;
+
+    // A release stops the repeat even if it was dropped, the key is no longer down
+    let mut repeat = REPEAT.lock();
+    let stop = match *repeat {
+        Some(ref r) => is_pressed || (r.scan == key && r.extended == extended),
+        None => false,
+    };
+    if stop {
+        if let Some(r) = repeat.take() {
+            timer::cancel(r.timer);
+        }
+    }
+
+    if is_pressed && stored {
+        let start = timer::uptime() + REPEAT_DELAY;
+        if let Ok(timer) = timer::call_every(start, REPEAT_PERIOD, repeat_key) {
+            repeat.replace(Repeat {
+                extended,
+                scan,
+                timer,
+            });
+        }
+    }
+}
+
+/// Called by the repeat timer
+fn repeat_key() {
+    if let Some(ref r) = *REPEAT.lock() {
+        BUFFER.lock().write(r.scan, r.extended);
+    }
+}
+
+/// Number of scans lost by the `getkey` buffer since boot
+pub fn dropped_scans() -> usize {
+    BUFFER.lock().dropped()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u8 = 0x1E;
+    const B: u8 = 0x30;
+    const C: u8 = 0x2E;
+    const D: u8 = 0x20;
+    const RELEASE: u8 = 0b10000000;
+
+    fn read_all<const SIZE: usize>(buffer: &mut ScanBuffer<SIZE>) -> ([u8; SIZE], usize) {
+        let mut scans = [0; SIZE];
+        let mut count = 0;
+        while let Some(scan) = buffer.read() {
+            scans[count] = scan;
+            count += 1;
+        }
+        (scans, count)
+    }
+
+    #[test_case]
+    fn full_buffer() {
+        let mut buffer = ScanBuffer::<4>::new();
+        for &scan in &[A, B, C, D] {
+            assert!(buffer.write(scan, false));
+        }
+        assert!(buffer.is_full());
+
+        // No repeat to evict
+        assert!(!buffer.write(A | RELEASE, false));
+        assert_eq!(buffer.dropped(), 1);
+        assert_eq!(read_all(&mut buffer), ([A, B, C, D], 4));
+    }
+
+    #[test_case]
+    fn repeat_eviction() {
+        let mut buffer = ScanBuffer::<4>::new();
+        assert!(buffer.write(A, false));
+        assert!(buffer.write(A, false));
+        assert!(buffer.write(B, false));
+        assert!(buffer.write(C, false));
+
+        // The release takes the place of the repeat, but a press doesn't
+        assert!(!buffer.write(D, false));
+        assert!(buffer.write(B | RELEASE, false));
+        assert_eq!(buffer.dropped(), 2);
+        assert_eq!(read_all(&mut buffer), ([A, B, C, B | RELEASE], 4));
+    }
+
+    #[test_case]
+    fn drop_accounting() {
+        let mut buffer = ScanBuffer::<4>::new();
+        assert!(buffer.write(A, false));
+        assert!(buffer.write(B, false));
+        assert!(buffer.write(C, false));
+
+        // Under pressure, repeats are dropped
+        assert!(!buffer.write(A, false));
+        assert_eq!(buffer.dropped(), 1);
+        assert!(buffer.write(D, false));
+
+        // A dropped release leaves the key held, as the reader didn't see it
+        assert!(!buffer.write(A | RELEASE, false));
+        assert_eq!(buffer.dropped(), 2);
+        assert!(buffer.is_held(A, false));
+    }
+
+    #[test_case]
+    fn extended_scans() {
+        const KEYPAD_8: u8 = 0x48;
+        const UP: u8 = 0x48;
+
+        let mut buffer = ScanBuffer::<4>::new();
+        assert!(buffer.write(UP, true));
+        assert!(buffer.is_held(UP, true));
+        assert!(!buffer.is_held(KEYPAD_8, false));
+        assert!(buffer.write(KEYPAD_8, false));
+        assert!(buffer.write(UP, true));
+
+        assert_eq!(buffer.read(), Some(SCAN_EXTENDED_PREFIX));
+        assert_eq!(buffer.read(), Some(UP));
+        assert_eq!(buffer.read(), Some(KEYPAD_8));
+
+        // The repeat whose prefix was read is not evicted
+        assert_eq!(buffer.read(), Some(SCAN_EXTENDED_PREFIX));
+        assert!(buffer.write(B, false));
+        assert!(buffer.write(C, false));
+        assert!(buffer.write(D, false));
+        assert!(!buffer.write(B | RELEASE, false));
+        assert_eq!(buffer.dropped(), 1);
+        assert_eq!(buffer.read(), Some(UP));
+
+        // A dropped extended scan doesn't leave its prefix
+        assert!(buffer.write(A, false));
+        assert!(!buffer.write(UP | RELEASE, true));
+        assert_eq!(buffer.dropped(), 2);
+        assert!(buffer.is_held(UP, true));
+        assert_eq!(read_all(&mut buffer), ([B, C, D, A], 4));
+    }
+
+    #[test_case]
+    fn capacity() {
+        let mut buffer = ScanBuffer::<8>::new();
+        assert!(buffer.write(A, false));
+        buffer.set_capacity(2);
+        assert_eq!(buffer.dropped(), 1);
+        assert!(buffer.is_empty());
+
+        assert!(buffer.write(B, false));
+        assert!(buffer.write(C, false));
+        assert!(buffer.is_full());
+        assert_eq!(read_all(&mut buffer).1, 2);
+    }
+
+    #[test_case]
+    fn release_dropped_then_pressed_again() {
+        let capacity = BUFFER.lock().capacity();
+        BUFFER.lock().set_capacity(4);
+        let dropped = dropped_scans();
+
+        for &scan in &[A, B, C, D] {
+            receive_scan(scan);
+        }
+        // Full, with no repeat to evict
+        receive_scan(A | RELEASE);
+        assert_eq!(dropped_scans(), dropped + 1);
+        while BUFFER.lock().read().is_some() {}
+
+        // The key is pressed again after the lost release
+        receive_scan(A);
+        assert_eq!(BUFFER.lock().read(), Some(A));
+
+        // Stop the repeat timer
+        for &scan in &[A, B, C, D] {
+            receive_scan(scan | RELEASE);
+        }
+        assert!(REPEAT.lock().is_none());
+        while BUFFER.lock().read().is_some() {}
+        BUFFER.lock().set_capacity(capacity);
+    }
 }
@@ -1,4 +1,7 @@
 pub mod keyboard;
+pub mod mouse;
+pub mod rtc;
+pub mod sb16;
 pub mod serial;
 pub mod speaker;
 pub mod timer;
@@ -0,0 +1,250 @@
+//! PS/2 mouse, plugged on the auxiliary port of the 8042 controller
+#![allow(dead_code)]
+
+use spin::Mutex;
+
+use crate::arch::i386::instructions::Port;
+use crate::interrupts;
+
+const IRQ: u8 = 12;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0b00000001;
+const STATUS_INPUT_FULL: u8 = 0b00000010;
+const STATUS_AUX_DATA: u8 = 0b00100000;
+
+const CONTROLLER_READ_CONFIG: u8 = 0x20;
+const CONTROLLER_WRITE_CONFIG: u8 = 0x60;
+const CONTROLLER_ENABLE_AUX: u8 = 0xA8;
+const CONTROLLER_WRITE_AUX: u8 = 0xD4;
+
+const CONFIG_AUX_INTERRUPT: u8 = 0b00000010;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 0b00100000;
+
+const MOUSE_GET_ID: u8 = 0xF2;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xF3;
+const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_SET_DEFAULTS: u8 = 0xF6;
+const MOUSE_ACK: u8 = 0xFA;
+
+/// Id returned by an IntelliMouse once the wheel is unlocked
+const MOUSE_ID_WHEEL: u8 = 3;
+
+/// Number of status polls before giving up on the controller
+const TIMEOUT: usize = 100_000;
+
+const PACKET_ALWAYS_ONE: u8 = 0b00001000;
+const PACKET_X_SIGN: u8 = 0b00010000;
+const PACKET_Y_SIGN: u8 = 0b00100000;
+const PACKET_X_OVERFLOW: u8 = 0b01000000;
+const PACKET_Y_OVERFLOW: u8 = 0b10000000;
+
+pub const BUTTON_LEFT: u8 = 1;
+pub const BUTTON_RIGHT: u8 = 2;
+pub const BUTTON_MIDDLE: u8 = 4;
+pub const WHEEL_UP: u8 = 8;
+pub const WHEEL_DOWN: u8 = 16;
+
+const BUTTONS_MASK: u8 = BUTTON_LEFT | BUTTON_RIGHT | BUTTON_MIDDLE;
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+
+/// Motion and buttons accumulated since the last call to `take_event`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseEvent {
+    /// Horizontal motion, positive to the right
+    pub dx: i32,
+    /// Vertical motion, positive upward like the PS/2 protocol
+    pub dy: i32,
+    /// Buttons held down, or pressed since the last event, and wheel motion
+    pub buttons: u8,
+}
+
+struct Mouse {
+    /// 3 or 4 bytes depending on the wheel, 0 if there is no mouse
+    packet_size: usize,
+    packet: [u8; 4],
+    index: usize,
+    dx: i32,
+    dy: i32,
+    wheel: i32,
+    buttons: u8,
+    /// Buttons pressed since the last event, so short clicks are not missed
+    clicked: u8,
+}
+
+impl Mouse {
+    const fn new() -> Self {
+        Mouse {
+            packet_size: 0,
+            packet: [0; 4],
+            index: 0,
+            dx: 0,
+            dy: 0,
+            wheel: 0,
+            buttons: 0,
+            clicked: 0,
+        }
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        if self.packet_size == 0 {
+            return;
+        }
+
+        // Resynchronize on the first byte of a packet
+        if self.index == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return;
+        }
+
+        self.packet[self.index] = byte;
+        self.index += 1;
+
+        if self.index == self.packet_size {
+            self.index = 0;
+            self.parse_packet();
+        }
+    }
+
+    fn parse_packet(&mut self) {
+        let flags = self.packet[0];
+
+        if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) == 0 {
+            self.dx += self.packet[1] as i32 - if flags & PACKET_X_SIGN != 0 { 0x100 } else { 0 };
+            self.dy += self.packet[2] as i32 - if flags & PACKET_Y_SIGN != 0 { 0x100 } else { 0 };
+        }
+
+        if self.packet_size == 4 {
+            // The wheel motion is a signed 4 bits value
+            self.wheel += ((self.packet[3] << 4) as i8 >> 4) as i32;
+        }
+
+        let buttons = flags & BUTTONS_MASK;
+        self.clicked |= buttons & !self.buttons;
+        self.buttons = buttons;
+    }
+
+    fn take_event(&mut self) -> MouseEvent {
+        let mut buttons = self.buttons | self.clicked;
+        // Like the PS/2 protocol, a negative wheel motion scrolls up
+        if self.wheel < 0 {
+            buttons |= WHEEL_UP;
+        } else if self.wheel > 0 {
+            buttons |= WHEEL_DOWN;
+        }
+
+        let event = MouseEvent {
+            dx: self.dx,
+            dy: self.dy,
+            buttons,
+        };
+
+        self.dx = 0;
+        self.dy = 0;
+        self.wheel = 0;
+        self.clicked = 0;
+
+        event
+    }
+}
+
+/// Enable the auxiliary port and the mouse reports.
+/// Must be called before interrupts are enabled, so the keyboard doesn't take the answers.
+pub fn init() {
+    match enable() {
+        Ok(packet_size) => {
+            info!("PS/2 mouse detected ({} bytes packets)", packet_size);
+            MOUSE.lock().packet_size = packet_size;
+            interrupts::register_irq(IRQ, on_interrupt).expect("The mouse IRQ is free");
+        }
+        Err(()) => warn!("No PS/2 mouse detected"),
+    }
+}
+
+fn enable() -> Result<usize, ()> {
+    send_controller_command(CONTROLLER_ENABLE_AUX)?;
+
+    send_controller_command(CONTROLLER_READ_CONFIG)?;
+    let config = (read_data()? | CONFIG_AUX_INTERRUPT) & !CONFIG_AUX_CLOCK_DISABLED;
+    send_controller_command(CONTROLLER_WRITE_CONFIG)?;
+    write_data(config)?;
+
+    send_mouse_command(MOUSE_SET_DEFAULTS)?;
+
+    // The magic sample rate sequence unlocks the wheel of IntelliMouse compatible mice
+    for &rate in [200, 100, 80].iter() {
+        send_mouse_command(MOUSE_SET_SAMPLE_RATE)?;
+        send_mouse_command(rate)?;
+    }
+    send_mouse_command(MOUSE_GET_ID)?;
+    let packet_size = if read_data()? == MOUSE_ID_WHEEL { 4 } else { 3 };
+
+    send_mouse_command(MOUSE_ENABLE_REPORTING)?;
+
+    Ok(packet_size)
+}
+
+fn send_controller_command(command: u8) -> Result<(), ()> {
+    wait_for_status(STATUS_INPUT_FULL, 0)?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(command) };
+    Ok(())
+}
+
+fn send_mouse_command(command: u8) -> Result<(), ()> {
+    send_controller_command(CONTROLLER_WRITE_AUX)?;
+    write_data(command)?;
+
+    if read_data()? == MOUSE_ACK {
+        Ok(())
+    } else {
+        Err(())
+    }
+}
+
+fn write_data(data: u8) -> Result<(), ()> {
+    wait_for_status(STATUS_INPUT_FULL, 0)?;
+    unsafe { Port::<u8>::new(DATA_PORT).write(data) };
+    Ok(())
+}
+
+fn read_data() -> Result<u8, ()> {
+    wait_for_status(STATUS_OUTPUT_FULL, STATUS_OUTPUT_FULL)?;
+    Ok(unsafe { Port::<u8>::new(DATA_PORT).read() })
+}
+
+fn wait_for_status(mask: u8, expected: u8) -> Result<(), ()> {
+    let mut status = Port::<u8>::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & mask == expected {
+            return Ok(());
+        }
+    }
+
+    Err(())
+}
+
+/// Returns true if the controller holds a byte sent by the mouse
+pub fn has_aux_data(status: u8) -> bool {
+    status & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) == STATUS_OUTPUT_FULL | STATUS_AUX_DATA
+}
+
+fn on_interrupt() {
+    let status = unsafe { Port::<u8>::new(STATUS_PORT).read() };
+    if has_aux_data(status) {
+        let byte = unsafe { Port::new(DATA_PORT).read() };
+        MOUSE.lock().receive_byte(byte);
+    }
+}
+
+/// Returns the motion and buttons since the last call, or None if there is no mouse
+pub fn take_event() -> Option<MouseEvent> {
+    let mut mouse = MOUSE.lock();
+    if mouse.packet_size == 0 {
+        None
+    } else {
+        Some(mouse.take_event())
+    }
+}
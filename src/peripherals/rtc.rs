@@ -0,0 +1,266 @@
+//! CMOS real-time clock
+#![allow(dead_code)]
+
+use core::fmt;
+
+use crate::arch::i386::instructions::Port;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Keep NMI disabled while a register is selected, it is enabled again after the access
+const NMI_DISABLE: u8 = 0x80;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+/// Not part of the original RTC, but where QEMU and most BIOSes keep it. Its actual location
+/// is in the ACPI FADT, which the kernel doesn't read.
+const REGISTER_CENTURY: u8 = 0x32;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24_HOURS: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+
+const HOURS_PM: u8 = 0x80;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since the Unix epoch
+    pub fn timestamp(&self) -> i64 {
+        days_from_civil(self.year as i64, self.month as i64, self.day as i64) * SECONDS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64
+    }
+
+    pub fn from_timestamp(timestamp: i64) -> DateTime {
+        let days = timestamp.div_euclid(SECONDS_PER_DAY);
+        let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Read the current date from the CMOS
+pub fn read() -> DateTime {
+    // Read until two consecutive reads agree, so we don't catch an update halfway
+    let mut date = read_registers();
+    loop {
+        let again = read_registers();
+        if again == date {
+            break;
+        }
+        date = again;
+    }
+
+    decode(
+        date,
+        read_register(REGISTER_STATUS_B),
+        read_register(REGISTER_CENTURY),
+    )
+}
+
+/// Convert the raw values of the time registers, as given by the format of the status B
+fn decode(date: DateTime, status_b: u8, century: u8) -> DateTime {
+    let decode = |value: u8| {
+        if status_b & STATUS_B_BINARY != 0 {
+            value
+        } else {
+            bcd_to_binary(value)
+        }
+    };
+
+    let mut hour = decode(date.hour & !HOURS_PM);
+    if status_b & STATUS_B_24_HOURS == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour %= 12;
+        if date.hour & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    // The century register is only trusted if it holds a plausible value. Otherwise the
+    // RTC is assumed to never go back before the Unix epoch, so the years are 1970 to 2069.
+    let year = decode(date.year as u8) as u16;
+    let year = match decode(century) as u16 {
+        century @ 19..=99 => century * 100 + year,
+        _ if year < 70 => 2000 + year,
+        _ => 1900 + year,
+    };
+
+    DateTime {
+        year,
+        month: decode(date.month),
+        day: decode(date.day),
+        hour,
+        minute: decode(date.minute),
+        second: decode(date.second),
+    }
+}
+
+/// Raw values of the time registers
+fn read_registers() -> DateTime {
+    while read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+
+    DateTime {
+        year: read_register(REGISTER_YEAR) as u16,
+        month: read_register(REGISTER_MONTH),
+        day: read_register(REGISTER_DAY),
+        hour: read_register(REGISTER_HOURS),
+        minute: read_register(REGISTER_MINUTES),
+        second: read_register(REGISTER_SECONDS),
+    }
+}
+
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        let mut index = Port::<u8>::new(CMOS_INDEX);
+        index.write(NMI_DISABLE | register);
+        let value = Port::<u8>::new(CMOS_DATA).read();
+        index.write(register);
+        value
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xF)
+}
+
+// From http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BCD: u8 = 0;
+    const BINARY_24_HOURS: u8 = STATUS_B_BINARY | STATUS_B_24_HOURS;
+
+    fn raw(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test_case]
+    fn bcd_and_binary() {
+        let date = raw(2024, 2, 29, 13, 45, 59);
+        let bcd = raw(0x24, 0x02, 0x29, 0x13, 0x45, 0x59);
+        let binary = raw(24, 2, 29, 13, 45, 59);
+
+        assert_eq!(decode(bcd, BCD | STATUS_B_24_HOURS, 0x20), date);
+        assert_eq!(decode(binary, BINARY_24_HOURS, 20), date);
+    }
+
+    #[test_case]
+    fn twelve_hours() {
+        let hour = |hour: u8, status_b: u8| decode(raw(0, 1, 1, hour, 0, 0), status_b, 20).hour;
+
+        // 12 AM is midnight and 12 PM is noon
+        assert_eq!(hour(0x12, BCD), 0);
+        assert_eq!(hour(0x01, BCD), 1);
+        assert_eq!(hour(0x12 | HOURS_PM, BCD), 12);
+        assert_eq!(hour(0x11 | HOURS_PM, BCD), 23);
+        assert_eq!(hour(12, STATUS_B_BINARY), 0);
+        assert_eq!(hour(12 | HOURS_PM, STATUS_B_BINARY), 12);
+        assert_eq!(hour(7 | HOURS_PM, STATUS_B_BINARY), 19);
+    }
+
+    #[test_case]
+    fn century() {
+        let year = |year: u8, century: u8| {
+            decode(raw(year as u16, 1, 1, 0, 0, 0), BINARY_24_HOURS, century).year
+        };
+
+        assert_eq!(year(99, 19), 1999);
+        assert_eq!(year(5, 21), 2105);
+        // Without a plausible century, the years are 1970 to 2069
+        assert_eq!(year(69, 0), 2069);
+        assert_eq!(year(70, 0), 1970);
+        assert_eq!(year(70, 0xFF), 1970);
+        assert_eq!(year(5, 100), 2005);
+    }
+
+    #[test_case]
+    fn leap_years() {
+        let february = |year| days_from_civil(year, 3, 1) - days_from_civil(year, 2, 1);
+
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(february(2000), 29);
+        assert_eq!(february(1900), 28);
+        assert_eq!(february(2024), 29);
+        assert_eq!(february(2023), 28);
+
+        for &(year, month, day) in &[(2000, 2, 29), (2024, 2, 29), (2100, 3, 1), (1969, 12, 31)] {
+            assert_eq!(
+                civil_from_days(days_from_civil(year, month, day)),
+                (year, month, day)
+            );
+        }
+
+        let date = raw(2024, 2, 29, 23, 59, 59);
+        assert_eq!(date.timestamp(), 1709251199);
+        assert_eq!(DateTime::from_timestamp(date.timestamp()), date);
+    }
+}
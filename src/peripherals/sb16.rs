@@ -0,0 +1,333 @@
+//! Sound Blaster 16, as emulated by QEMU's `-device sb16`
+//!
+//! Samples are played from a DMA buffer split in two halves: the DSP raises an
+//! interrupt each time it finishes one, which is then refilled from the queue.
+#![allow(dead_code)]
+
+use core::cmp::min;
+
+use spin::Mutex;
+
+use super::timer;
+
+use crate::arch::i386::dma;
+use crate::arch::i386::instructions::Port;
+use crate::interrupts;
+
+const IRQ: u8 = 5;
+const BASE: u16 = 0x220;
+const MIXER_ADDRESS: u16 = BASE + 0x4;
+const MIXER_DATA: u16 = BASE + 0x5;
+const DSP_RESET: u16 = BASE + 0x6;
+const DSP_READ: u16 = BASE + 0xA;
+const DSP_WRITE: u16 = BASE + 0xC;
+/// Reading it also acknowledges 8 bits interrupts
+const DSP_READ_STATUS: u16 = BASE + 0xE;
+const DSP_ACK_16: u16 = BASE + 0xF;
+
+const DSP_READY: u8 = 0xAA;
+const STATUS_BUSY: u8 = 0x80;
+
+const COMMAND_SET_OUTPUT_RATE: u8 = 0x41;
+const COMMAND_OUTPUT_16: u8 = 0xB6;
+const COMMAND_OUTPUT_8: u8 = 0xC6;
+const COMMAND_SPEAKER_ON: u8 = 0xD1;
+const COMMAND_EXIT_AUTO_INIT_16: u8 = 0xD9;
+const COMMAND_EXIT_AUTO_INIT_8: u8 = 0xDA;
+const COMMAND_GET_VERSION: u8 = 0xE1;
+
+const MODE_SIGNED: u8 = 0x10;
+const MODE_STEREO: u8 = 0x20;
+
+const MIXER_IRQ: u8 = 0x80;
+const MIXER_DMA: u8 = 0x81;
+const MIXER_IRQ_5: u8 = 0x02;
+const MIXER_DMA_1_5: u8 = 0x22;
+
+const DMA_CHANNEL_8: u8 = 1;
+const DMA_CHANNEL_16: u8 = 5;
+
+/// Number of status polls before giving up on the DSP
+const TIMEOUT: usize = 100_000;
+
+/// Size of each half of the DMA buffer
+const BLOCK_SIZE: usize = 4096;
+/// Samples submitted but not yet copied to the DMA buffer
+const QUEUE_SIZE: usize = 64 * 1024;
+
+/// The DMA buffer must not cross a 64 KiB boundary, which its alignment guarantees
+#[repr(C, align(8192))]
+struct DmaBuffer([u8; 2 * BLOCK_SIZE]);
+
+static mut DMA_BUFFER: DmaBuffer = DmaBuffer([0; 2 * BLOCK_SIZE]);
+
+static SB16: Mutex<Sb16> = Mutex::new(Sb16::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Format {
+    /// Sample rate in hertz
+    pub rate: u16,
+    /// 8 bits samples are unsigned, 16 bits samples are signed
+    pub bits: u8,
+    /// 1 for mono, 2 for stereo, with interleaved samples
+    pub channels: u8,
+}
+
+impl Format {
+    fn is_valid(&self) -> bool {
+        (self.bits == 8 || self.bits == 16)
+            && (self.channels == 1 || self.channels == 2)
+            && self.rate >= 5000
+            && self.rate <= 44100
+    }
+
+    /// Size of a sample for every channel, in bytes
+    fn frame_size(&self) -> usize {
+        self.bits as usize / 8 * self.channels as usize
+    }
+
+    fn silence(&self) -> u8 {
+        if self.bits == 8 {
+            0x80
+        } else {
+            0
+        }
+    }
+}
+
+struct Sb16 {
+    present: bool,
+    format: Option<Format>,
+    playing: bool,
+    /// Half of the DMA buffer to refill at the next interrupt
+    next_block: usize,
+    /// Blocks filled only with silence in a row, to stop when the queue stays empty
+    silent_blocks: usize,
+    queue: [u8; QUEUE_SIZE],
+    queue_start: usize,
+    queue_len: usize,
+}
+
+impl Sb16 {
+    const fn new() -> Self {
+        Sb16 {
+            present: false,
+            format: None,
+            playing: false,
+            next_block: 0,
+            silent_blocks: 0,
+            queue: [0; QUEUE_SIZE],
+            queue_start: 0,
+            queue_len: 0,
+        }
+    }
+
+    /// Queue as many whole frames as possible, returns the number of bytes queued
+    fn submit(&mut self, samples: &[u8], format: Format) -> usize {
+        if self.format != Some(format) {
+            self.stop();
+            self.queue_len = 0;
+            self.format = Some(format);
+        }
+
+        let free = QUEUE_SIZE - self.queue_len;
+        let length = min(samples.len(), free) / format.frame_size() * format.frame_size();
+
+        for &sample in samples[..length].iter() {
+            self.queue[(self.queue_start + self.queue_len) % QUEUE_SIZE] = sample;
+            self.queue_len += 1;
+        }
+
+        if !self.playing && self.queue_len != 0 {
+            self.start(format);
+        }
+
+        length
+    }
+
+    fn start(&mut self, format: Format) {
+        self.fill_block(0);
+        self.fill_block(1);
+        self.next_block = 0;
+
+        let (channel, command, mode, block_samples) = if format.bits == 8 {
+            (DMA_CHANNEL_8, COMMAND_OUTPUT_8, 0, BLOCK_SIZE)
+        } else {
+            (DMA_CHANNEL_16, COMMAND_OUTPUT_16, MODE_SIGNED, BLOCK_SIZE / 2)
+        };
+        let mode = if format.channels == 2 {
+            mode | MODE_STEREO
+        } else {
+            mode
+        };
+
+        let result = unsafe {
+            dma::setup_auto_init_read(channel, DMA_BUFFER.0.as_ptr() as u32, 2 * BLOCK_SIZE as u32)
+        };
+        if result.is_err() {
+            warn!("The SB16 DMA buffer is not reachable by the DMA controller");
+            return;
+        }
+
+        // Interrupt at the end of each half, while the other one plays
+        let count = block_samples - 1;
+        let started = write_dsp(COMMAND_SET_OUTPUT_RATE)
+            .and_then(|_| write_dsp((format.rate >> 8) as u8))
+            .and_then(|_| write_dsp(format.rate as u8))
+            .and_then(|_| write_dsp(command))
+            .and_then(|_| write_dsp(mode))
+            .and_then(|_| write_dsp(count as u8))
+            .and_then(|_| write_dsp((count >> 8) as u8));
+
+        self.playing = started.is_ok();
+    }
+
+    fn stop(&mut self) {
+        if let (true, Some(format)) = (self.playing, self.format) {
+            let (channel, command) = if format.bits == 8 {
+                (DMA_CHANNEL_8, COMMAND_EXIT_AUTO_INIT_8)
+            } else {
+                (DMA_CHANNEL_16, COMMAND_EXIT_AUTO_INIT_16)
+            };
+
+            write_dsp(command).ok();
+            unsafe { dma::mask(channel) };
+        }
+
+        self.playing = false;
+    }
+
+    /// Copy the queued samples in the half of the DMA buffer, and complete it with silence
+    fn fill_block(&mut self, block: usize) {
+        let silence = self.format.map(|f| f.silence()).unwrap_or(0);
+        let buffer = unsafe { &mut DMA_BUFFER.0[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE] };
+
+        let length = min(self.queue_len, BLOCK_SIZE);
+        for byte in buffer[..length].iter_mut() {
+            *byte = self.queue[self.queue_start];
+            self.queue_start = (self.queue_start + 1) % QUEUE_SIZE;
+        }
+        self.queue_len -= length;
+
+        for byte in buffer[length..].iter_mut() {
+            *byte = silence;
+        }
+
+        self.silent_blocks = if length == 0 { self.silent_blocks + 1 } else { 0 };
+    }
+
+    fn on_interrupt(&mut self) {
+        if let Some(format) = self.format {
+            let ack = if format.bits == 8 { DSP_READ_STATUS } else { DSP_ACK_16 };
+            unsafe { Port::<u8>::new(ack).read() };
+        }
+
+        if !self.playing {
+            return;
+        }
+
+        let block = self.next_block;
+        self.fill_block(block);
+        self.next_block ^= 1;
+
+        // Both halves are silent, nothing is left to play
+        if self.silent_blocks >= 2 {
+            self.stop();
+        }
+    }
+}
+
+/// Reset the DSP and route it to IRQ 5 and DMA channels 1 and 5
+pub fn init() {
+    match reset() {
+        Ok((major, minor)) if major >= 4 => {
+            info!("Sound Blaster 16 detected (DSP {}.{:02})", major, minor);
+            write_mixer(MIXER_IRQ, MIXER_IRQ_5);
+            write_mixer(MIXER_DMA, MIXER_DMA_1_5);
+            write_dsp(COMMAND_SPEAKER_ON).ok();
+            SB16.lock().present = true;
+            interrupts::register_irq(IRQ, on_interrupt).expect("The Sound Blaster IRQ is free");
+        }
+        Ok((major, minor)) => warn!("Unsupported Sound Blaster DSP {}.{:02}", major, minor),
+        Err(()) => info!("No Sound Blaster detected"),
+    }
+}
+
+/// Returns the version of the DSP
+fn reset() -> Result<(u8, u8), ()> {
+    let mut reset = Port::<u8>::new(DSP_RESET);
+    unsafe {
+        reset.write(1);
+        // Wait at least 3 microseconds, each port access takes about one
+        for _ in 0..4 {
+            Port::<u8>::new(DSP_READ_STATUS).read();
+        }
+        reset.write(0);
+    }
+
+    if read_dsp()? != DSP_READY {
+        return Err(());
+    }
+
+    write_dsp(COMMAND_GET_VERSION)?;
+    Ok((read_dsp()?, read_dsp()?))
+}
+
+fn write_dsp(value: u8) -> Result<(), ()> {
+    let mut write = Port::<u8>::new(DSP_WRITE);
+    for _ in 0..TIMEOUT {
+        unsafe {
+            if write.read() & STATUS_BUSY == 0 {
+                write.write(value);
+                return Ok(());
+            }
+        }
+    }
+
+    Err(())
+}
+
+fn read_dsp() -> Result<u8, ()> {
+    let mut status = Port::<u8>::new(DSP_READ_STATUS);
+    for _ in 0..TIMEOUT {
+        unsafe {
+            if status.read() & STATUS_BUSY != 0 {
+                return Ok(Port::<u8>::new(DSP_READ).read());
+            }
+        }
+    }
+
+    Err(())
+}
+
+fn write_mixer(register: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(MIXER_ADDRESS).write(register);
+        Port::<u8>::new(MIXER_DATA).write(value);
+    }
+}
+
+/// Queue samples to play after the previous ones.
+/// A change of format drops the samples not yet played.
+///
+/// Returns the number of bytes queued, which is less than the length of the samples when
+/// the queue is full, or an error if there is no Sound Blaster or the format is not supported.
+pub fn submit(samples: &[u8], format: Format) -> Result<usize, ()> {
+    if !format.is_valid() {
+        return Err(());
+    }
+
+    timer::without_interrupts(|| {
+        let mut sb16 = SB16.lock();
+        if sb16.present {
+            Ok(sb16.submit(samples, format))
+        } else {
+            Err(())
+        }
+    })
+}
+
+/// Called at the end of each half of the DMA buffer
+fn on_interrupt() {
+    SB16.lock().on_interrupt();
+}
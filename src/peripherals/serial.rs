@@ -9,6 +9,7 @@ use spin::Mutex;
 use crate::arch::i386::instructions::Port;
 
 const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
 
 lazy_static! {
     pub static ref SERIAL_PORT: Mutex<SerialPort> = Mutex::new(SerialPort::default());
@@ -24,16 +25,19 @@ pub struct SerialPort {
     ier_and_dlm: Port<u8>,
     iir_and_fcr: Port<u8>,
     lcr: Port<u8>,
+    mcr: Port<u8>,
+    lsr: Port<u8>,
 }
 
-// TODO Implement read
 impl SerialPort {
-    fn new_uart_16550(base: u16) -> SerialPort {
+    pub fn new_uart_16550(base: u16) -> SerialPort {
         let mut serial_port = SerialPort {
             rbr_thr_and_dll: Port::new(base),
             ier_and_dlm: Port::new(base + 1),
             iir_and_fcr: Port::new(base + 2),
             lcr: Port::new(base + 3),
+            mcr: Port::new(base + 4),
+            lsr: Port::new(base + 5),
         };
 
         serial_port.init_uart_16550();
@@ -74,6 +78,43 @@ impl SerialPort {
         // TODO: Wait for ack ?
         unsafe { self.rbr_thr_and_dll.write(byte) };
     }
+
+    /// Returns the next received byte, if any
+    pub fn read_byte(&mut self) -> Option<u8> {
+        unsafe {
+            if LSR(self.lsr.read()).data_ready() {
+                Some(self.rbr_thr_and_dll.read())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Wait for the next received byte
+    pub fn wait_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    /// Raise an interrupt when a byte is received, instead of when a byte is sent
+    pub fn enable_receive_interrupt(&mut self) {
+        let mut ier = IER(0);
+        ier.set_receive_data_available(true);
+
+        let mut mcr = MCR(0);
+        mcr.set_data_terminal_ready(true);
+        mcr.set_request_to_send(true);
+        // Connects the interrupt line of the UART to the PIC
+        mcr.set_out2(true);
+
+        unsafe {
+            self.ier_and_dlm.write(ier.0);
+            self.mcr.write(mcr.0);
+        }
+    }
 }
 
 impl Default for SerialPort {
@@ -127,3 +168,28 @@ bitfield! {
     sleep_mode, set_sleep_mode: 4;
     low_power_mode, set_low_power_mode: 5;
 }
+
+bitfield! {
+    struct MCR(u8);
+    impl Debug;
+
+    data_terminal_ready, set_data_terminal_ready: 0;
+    request_to_send, set_request_to_send: 1;
+    out1, set_out1: 2;
+    out2, set_out2: 3;
+    loopback, set_loopback: 4;
+}
+
+bitfield! {
+    struct LSR(u8);
+    impl Debug;
+
+    data_ready, _: 0;
+    overrun_error, _: 1;
+    parity_error, _: 2;
+    framing_error, _: 3;
+    break_interrupt, _: 4;
+    transmit_holding_register_empty, _: 5;
+    transmitter_empty, _: 6;
+    fifo_error, _: 7;
+}
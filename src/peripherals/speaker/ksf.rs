@@ -0,0 +1,55 @@
+//! KSF sound files, as produced by `tools/mkksf`: the ".KSF" magic, the number
+//! of tones, then a frequency and a duration for each tone, all little endian u32.
+
+use alloc::vec::Vec;
+
+use no_std_io::Read;
+
+use super::{Error, Result, Tone, MAX_MELODY_LEN};
+
+const MAGIC: &[u8; 4] = b".KSF";
+
+/// Read the tones of the file
+///
+/// The file is read tone by tone instead of being copied whole, but all the tones are
+/// returned, as the melody engine plays them from memory.
+pub fn read<R: Read>(file: &mut R) -> Result<Vec<Tone>> {
+    let mut magic = [0; 4];
+    read_exact(file, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::InvalidFile);
+    }
+
+    let count = read_u32(file)? as usize;
+    if count > MAX_MELODY_LEN {
+        return Err(Error::TooLong);
+    }
+
+    let mut tones = Vec::with_capacity(count);
+    for _ in 0..count {
+        let frequency = read_u32(file)?;
+        let duration = read_u32(file)?;
+
+        // A null frequency ends the melody, like for in-memory melodies
+        if frequency == 0 {
+            break;
+        }
+        tones.push(Tone::new(frequency, duration));
+    }
+
+    Ok(tones)
+}
+
+fn read_u32<R: Read>(file: &mut R) -> Result<u32> {
+    let mut bytes = [0; 4];
+    read_exact(file, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// KFS readers only return less than asked at the end of the file
+fn read_exact<R: Read>(file: &mut R, buffer: &mut [u8]) -> Result<()> {
+    match file.read(buffer) {
+        Ok(size) if size == buffer.len() => Ok(()),
+        _ => Err(Error::InvalidFile),
+    }
+}
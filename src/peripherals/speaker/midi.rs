@@ -0,0 +1,430 @@
+//! Standard MIDI files, reduced to a single voice: the highest note held at each instant,
+//! across every track or in a single one
+//!
+//! Running status, tempo changes and SMPTE time divisions are supported. The percussion
+//! channel is ignored, as its notes are not pitches.
+
+use alloc::vec::Vec;
+
+use super::{Error, Result, Tone, MAX_MELODY_LEN};
+
+pub const MAGIC: &[u8; 4] = b"MThd";
+const TRACK_MAGIC: &[u8; 4] = b"MTrk";
+
+/// Microseconds per quarter note until the first tempo event, 120 beats per minute
+const DEFAULT_TEMPO: u32 = 500_000;
+/// Channel 10, as numbered by musicians
+const PERCUSSION_CHANNEL: u8 = 9;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const PROGRAM_CHANGE: u8 = 0xC0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const SYSEX: u8 = 0xF0;
+const SYSEX_ESCAPE: u8 = 0xF7;
+const META: u8 = 0xFF;
+
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+
+#[derive(Clone, Copy)]
+enum EventKind {
+    NoteOff(u8),
+    NoteOn(u8),
+    /// Microseconds per quarter note
+    Tempo(u32),
+}
+
+#[derive(Clone, Copy)]
+struct Event {
+    tick: u32,
+    kind: EventKind,
+}
+
+/// Convert the file to tones. With `track`, only the notes of this track are played, but
+/// the tempo changes of every track still apply, as they are usually in the first one.
+pub fn read(data: &[u8], track: Option<usize>) -> Result<Vec<Tone>> {
+    let mut cursor = Cursor { data, offset: 0 };
+
+    let (header, header_length) = cursor.chunk_header()?;
+    if &header != MAGIC || header_length < 6 {
+        return Err(Error::InvalidFile);
+    }
+    let mut header = cursor.sub_cursor(header_length)?;
+    let _format = header.u16()?;
+    let track_count = header.u16()?;
+    let division = header.u16()?;
+
+    // Either ticks per quarter note, or frames per second and ticks per frame
+    let timing = if division & 0x8000 == 0 {
+        Timing::PerQuarter(division as u32)
+    } else {
+        // The negated frame rate, 29 standing for the drop frame 29.97
+        let frames = match (division >> 8) as u8 as i8 {
+            -24 => 24,
+            -25 => 25,
+            -29 => 29,
+            -30 => 30,
+            _ => return Err(Error::InvalidFile),
+        };
+        Timing::PerSecond(frames * (division & 0xFF) as u32)
+    };
+    if timing.is_null() {
+        return Err(Error::InvalidFile);
+    }
+
+    let mut events = Vec::new();
+    let mut index = 0;
+    while index < track_count as usize {
+        let (magic, length) = cursor.chunk_header()?;
+        let chunk = cursor.sub_cursor(length)?;
+        // Unknown chunks are skipped, as the format requires
+        if &magic != TRACK_MAGIC {
+            continue;
+        }
+
+        let notes = track.map(|t| t == index).unwrap_or(true);
+        read_track(chunk, notes, &mut events)?;
+        index += 1;
+    }
+
+    // Stable, so the events of a tick keep the order of their track
+    events.sort_by_key(|e| e.tick);
+
+    to_tones(&events, timing)
+}
+
+fn read_track(mut track: Cursor, notes: bool, events: &mut Vec<Event>) -> Result<()> {
+    let mut tick = 0u32;
+    let mut running_status = None;
+
+    while !track.is_empty() {
+        tick = tick.saturating_add(track.variable_length()?);
+
+        let mut status = track.u8()?;
+        let first_data = if status & 0x80 == 0 {
+            // Running status, the byte is the first data byte
+            let data = status;
+            status = running_status.ok_or(Error::InvalidFile)?;
+            Some(data)
+        } else {
+            None
+        };
+
+        match status {
+            META => {
+                let typ = track.u8()?;
+                let length = track.variable_length()? as usize;
+                let data = track.bytes(length)?;
+                match typ {
+                    META_END_OF_TRACK => break,
+                    META_TEMPO if length == 3 => {
+                        let tempo = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+                        events.push(Event {
+                            tick,
+                            kind: EventKind::Tempo(tempo),
+                        });
+                    }
+                    _ => (),
+                }
+            }
+            SYSEX | SYSEX_ESCAPE => {
+                let length = track.variable_length()? as usize;
+                track.bytes(length)?;
+            }
+            // System common and real time messages have no place in a file
+            0xF1..=0xFE => return Err(Error::InvalidFile),
+            _ => {
+                running_status = Some(status);
+
+                let first = match first_data {
+                    Some(data) => data,
+                    None => track.u8()?,
+                };
+                let kind = status & 0xF0;
+                let second = if kind == PROGRAM_CHANGE || kind == CHANNEL_PRESSURE {
+                    0
+                } else {
+                    track.u8()?
+                };
+
+                if !notes || status & 0x0F == PERCUSSION_CHANNEL {
+                    continue;
+                }
+
+                let kind = match kind {
+                    NOTE_ON if second != 0 => EventKind::NoteOn(first & 0x7F),
+                    // A note on without velocity is a note off
+                    NOTE_ON | NOTE_OFF => EventKind::NoteOff(first & 0x7F),
+                    _ => continue,
+                };
+                events.push(Event { tick, kind });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Timing {
+    PerQuarter(u32),
+    PerSecond(u32),
+}
+
+impl Timing {
+    fn is_null(&self) -> bool {
+        match *self {
+            Timing::PerQuarter(ticks) | Timing::PerSecond(ticks) => ticks == 0,
+        }
+    }
+
+    /// Duration of the ticks in microseconds
+    fn duration(&self, ticks: u32, tempo: u32) -> u64 {
+        match *self {
+            Timing::PerQuarter(division) => ticks as u64 * tempo as u64 / division as u64,
+            Timing::PerSecond(rate) => ticks as u64 * 1_000_000 / rate as u64,
+        }
+    }
+}
+
+/// Play the highest held note between each pair of consecutive events
+fn to_tones(events: &[Event], timing: Timing) -> Result<Vec<Tone>> {
+    let mut tones = Vec::new();
+    let mut held = [0u8; 128];
+    let mut tempo = DEFAULT_TEMPO;
+    let mut tick = 0;
+    // Durations are rounded from the absolute time, so rounding errors do not add up
+    let mut time = 0u64;
+    let mut tone_start = 0u64;
+    let mut frequency = 0;
+
+    for event in events {
+        if event.tick != tick {
+            time += timing.duration(event.tick - tick, tempo);
+            tick = event.tick;
+        }
+
+        match event.kind {
+            EventKind::NoteOn(note) => held[note as usize] = held[note as usize].saturating_add(1),
+            EventKind::NoteOff(note) => held[note as usize] = held[note as usize].saturating_sub(1),
+            EventKind::Tempo(t) => tempo = t,
+        }
+
+        let next = held
+            .iter()
+            .rposition(|&n| n != 0)
+            .map(frequency_of)
+            .unwrap_or(0);
+        if next == frequency {
+            continue;
+        }
+
+        // A null duration would chord the tone with the next one
+        let duration = (time / 1000 - tone_start / 1000) as u32;
+        if duration != 0 {
+            // Leading silences are skipped
+            if frequency != 0 || !tones.is_empty() {
+                if tones.len() == MAX_MELODY_LEN {
+                    return Err(Error::TooLong);
+                }
+                tones.push(Tone::new(frequency, duration));
+            }
+            tone_start = time;
+        }
+        frequency = next;
+    }
+
+    // Trailing silences are useless, and would be taken for the end of a melody
+    while tones.last().map(|t| t.frequency == 0).unwrap_or(false) {
+        tones.pop();
+    }
+
+    Ok(tones)
+}
+
+/// Frequency in hertz of the MIDI note, from the A4 at 440 Hz
+fn frequency_of(note: usize) -> u32 {
+    // Equal temperament frequencies of the octave from C-1, in millihertz
+    const OCTAVE: [u32; 12] = [
+        8176, 8662, 9177, 9723, 10301, 10913, 11562, 12250, 12978, 13750, 14568, 15434,
+    ];
+
+    // The speaker can't go lower than the PIT frequency divided by 65535
+    let frequency = (OCTAVE[note % 12] << (note / 12)) / 1000;
+    if frequency < 19 {
+        19
+    } else {
+        frequency
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.offset.checked_add(length).ok_or(Error::InvalidFile)?;
+        let bytes = self.data.get(self.offset..end).ok_or(Error::InvalidFile)?;
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn sub_cursor(&mut self, length: usize) -> Result<Cursor<'a>> {
+        Ok(Cursor {
+            data: self.bytes(length)?,
+            offset: 0,
+        })
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Big endian quantity of 7 bits per byte, the last byte having its high bit cleared
+    fn variable_length(&mut self) -> Result<u32> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(Error::InvalidFile)
+    }
+
+    fn chunk_header(&mut self) -> Result<([u8; 4], usize)> {
+        let bytes = self.bytes(4)?;
+        let magic = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok((magic, self.u32()? as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    const END_OF_TRACK: &[u8] = &[0x00, META, META_END_OF_TRACK, 0x00];
+
+    /// File with the division and the tracks, each ended with an end of track event
+    fn file(division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&6u32.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        data.extend_from_slice(&division.to_be_bytes());
+
+        for track in tracks {
+            data.extend_from_slice(TRACK_MAGIC);
+            data.extend_from_slice(&((track.len() + END_OF_TRACK.len()) as u32).to_be_bytes());
+            data.extend_from_slice(track);
+            data.extend_from_slice(END_OF_TRACK);
+        }
+
+        data
+    }
+
+    fn tones(data: &[u8], track: Option<usize>) -> Result<Vec<(u32, u32)>> {
+        let tones = read(data, track)?;
+        Ok(tones.iter().map(|t| (t.frequency, t.duration)).collect())
+    }
+
+    /// A4 during 500 ticks, with 0x83 0x74 as variable length quantity
+    const A4: &[u8] = &[0x00, 0x90, 69, 0x40, 0x83, 0x74, 0x80, 69, 0x40];
+
+    #[test_case]
+    fn header() {
+        assert_eq!(tones(&file(500, &[A4]), None), Ok(vec![(440, 500)]));
+
+        let mut data = file(500, &[A4]);
+        data[0] = b'X';
+        assert_eq!(tones(&data, None).err(), Some(Error::InvalidFile));
+
+        let mut data = file(500, &[A4]);
+        data[7] = 5;
+        assert_eq!(tones(&data, None).err(), Some(Error::InvalidFile));
+
+        let data = file(500, &[A4]);
+        assert_eq!(
+            tones(&data[..data.len() - 1], None).err(),
+            Some(Error::InvalidFile)
+        );
+    }
+
+    #[test_case]
+    fn division() {
+        assert_eq!(tones(&file(1000, &[A4]), None), Ok(vec![(440, 250)]));
+        assert_eq!(tones(&file(0, &[A4]), None).err(), Some(Error::InvalidFile));
+
+        // A tempo of a second per quarter note
+        let track = [&[0x00, META, META_TEMPO, 3, 0x0F, 0x42, 0x40][..], A4].concat();
+        assert_eq!(
+            tones(&file(500, &[&track[..]]), None),
+            Ok(vec![(440, 1000)])
+        );
+
+        // 25 frames per second of 40 ticks, the tempo doesn't apply
+        assert_eq!(tones(&file(0xE728, &[A4]), None), Ok(vec![(440, 500)]));
+        assert_eq!(
+            tones(&file(0xE728, &[&track[..]]), None),
+            Ok(vec![(440, 500)])
+        );
+
+        for &division in &[0x8028, 0xE928, 0xFF28, 0xE800] {
+            assert_eq!(
+                tones(&file(division, &[A4]), None).err(),
+                Some(Error::InvalidFile)
+            );
+        }
+    }
+
+    #[test_case]
+    fn variable_length() {
+        let cases: &[(&[u8], Result<u32>)] = &[
+            (&[0x00], Ok(0)),
+            (&[0x7F], Ok(0x7F)),
+            (&[0x81, 0x00], Ok(0x80)),
+            (&[0xFF, 0xFF, 0xFF, 0x7F], Ok(0x0FFF_FFFF)),
+            (&[0x80, 0x80, 0x80, 0x80, 0x00], Err(Error::InvalidFile)),
+            (&[0x81], Err(Error::InvalidFile)),
+        ];
+
+        for &(data, value) in cases {
+            let mut cursor = Cursor { data, offset: 0 };
+            assert_eq!(cursor.variable_length(), value);
+        }
+    }
+
+    #[test_case]
+    fn tracks() {
+        // C4 during 96 ticks, and C5 during 48 ticks in the other track
+        let low: &[u8] = &[0x00, 0x90, 60, 0x40, 0x60, 0x80, 60, 0x40];
+        let high: &[u8] = &[0x00, 0x90, 72, 0x40, 0x30, 0x90, 72, 0x00];
+        let data = file(96, &[low, high]);
+
+        assert_eq!(tones(&data, None), Ok(vec![(523, 250), (261, 250)]));
+        assert_eq!(tones(&data, Some(0)), Ok(vec![(261, 500)]));
+        assert_eq!(tones(&data, Some(1)), Ok(vec![(523, 250)]));
+    }
+}
@@ -0,0 +1,533 @@
+#![allow(dead_code)]
+
+mod ksf;
+mod midi;
+
+use alloc::vec::Vec;
+use core::cmp::{min, Reverse};
+use core::mem::size_of;
+
+use no_std_io::{Read, Seek, SeekFrom};
+use spin::Mutex;
+
+use super::timer::{self, TimerId};
+
+use crate::arch::i386::instructions::Port;
+use crate::arch::i386::pit::PIT;
+use crate::userland::USER_PROCESS;
+
+/// Maximum number of queued melodies, including the playing one
+const QUEUE_SIZE: usize = 8;
+/// Maximum number of notes in a chord
+const MAX_CHORD: usize = 4;
+/// Time each note of a chord is played in turn, in milliseconds
+const ARPEGGIO_STEP: usize = 20;
+/// Maximum number of tones in a melody
+pub const MAX_MELODY_LEN: usize = 4096;
+/// Maximum size of the MIDI files, which are read whole
+const MAX_FILE_SIZE: usize = 256 * 1024;
+
+static PLAYER: Mutex<Player> = Mutex::new(Player::new());
+
+/// Represent a  not in the melody
+///
+/// A tone with a null duration is played along with the following tones, up to the
+/// next tone with a duration, as a chord. MIDI melodies use tones with a null frequency
+/// as rests, while KSF and user melodies end at the first one.
+#[derive(Clone, Debug)]
+#[repr(C)]
+pub struct Tone {
+    /// Frequency in hertz
+    pub frequency: u32,
+    /// Duration in milliseconds
+    pub duration: u32,
+}
+
+impl Tone {
+    pub const fn new(frequency: u32, duration: u32) -> Self {
+        Tone {
+            frequency,
+            duration,
+        }
+    }
+
+    fn is_end(&self) -> bool {
+        self.frequency == 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The melody is not in the memory of the program
+    InvalidPointer,
+    /// The melody is not terminated within `MAX_MELODY_LEN` tones
+    TooLong,
+    /// Too many melodies are already queued
+    QueueFull,
+    /// There is no such file in the filesystem
+    NotFound,
+    /// The file is not a valid KSF or MIDI file
+    InvalidFile,
+}
+
+type Result<T> = ::core::result::Result<T, Error>;
+
+/// Melodies of higher priority interrupt the others, which resume afterward
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Music = 0,
+    Effect = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Stopped = 0,
+    Playing = 1,
+    Paused = 2,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    pub state: State,
+    /// Priority of the current melody
+    pub priority: Priority,
+    /// Frequency currently played, 0 if there is none
+    pub frequency: u32,
+    /// Number of melodies in the queue, including the current one
+    pub queued: usize,
+}
+
+pub fn play_frequency(frequency: u32) {
+    PIT.lock().play_sound(frequency);
+}
+
+/// Copy tones terminated by a null frequency, which may be overwritten by userland anytime,
+/// so each of them is read once
+unsafe fn copy_melody(melody: *const Tone) -> Result<Vec<Tone>> {
+    if melody.is_null() {
+        return Err(Error::InvalidPointer);
+    }
+
+    let process = USER_PROCESS.lock();
+    let mut tones = Vec::new();
+    for i in 0..=MAX_MELODY_LEN {
+        let tone = melody.wrapping_add(i);
+        if !process.can_read(tone as usize, size_of::<Tone>()) {
+            return Err(Error::InvalidPointer);
+        }
+
+        let tone = (&*tone).clone();
+        if tone.is_end() {
+            return Ok(tones);
+        }
+        tones.push(tone);
+    }
+
+    Err(Error::TooLong)
+}
+
+pub fn start_melody_from(melody: *const Tone, repeating: bool) -> Result<()> {
+    let tones = unsafe { copy_melody(melody)? };
+    start(Melody::new(tones, repeating, Priority::Music));
+    Ok(())
+}
+
+pub fn queue_melody_from(melody: *const Tone, repeating: bool, priority: Priority) -> Result<()> {
+    let tones = unsafe { copy_melody(melody)? };
+    queue(Melody::new(tones, repeating, priority))
+}
+
+/// Stop everything and play the melody as music
+pub fn start_melody(melody: &[Tone], repeating: bool) -> Result<()> {
+    if melody.len() > MAX_MELODY_LEN {
+        return Err(Error::TooLong);
+    }
+
+    start(Melody::new(melody.to_vec(), repeating, Priority::Music));
+    Ok(())
+}
+
+/// Play the melody after the ones of the same priority
+pub fn queue_melody(melody: &[Tone], repeating: bool, priority: Priority) -> Result<()> {
+    if melody.len() > MAX_MELODY_LEN {
+        return Err(Error::TooLong);
+    }
+
+    queue(Melody::new(melody.to_vec(), repeating, priority))
+}
+
+/// Stop everything and play the KSF or MIDI file as music
+pub fn play_file(path: &str, repeating: bool) -> Result<()> {
+    let fs = crate::fs::get();
+    let inode = fs.find(path).ok_or(Error::NotFound)?;
+    let mut file = fs.reader(inode);
+
+    let mut magic = [0; 4];
+    let read = file.read(&mut magic);
+    file.seek(SeekFrom::Start(0)).map_err(|_| Error::InvalidFile)?;
+
+    let tones = match read {
+        Ok(4) if &magic == midi::MAGIC => midi::read(&read_to_end(&mut file)?, None)?,
+        _ => ksf::read(&mut file)?,
+    };
+
+    start(Melody::new(tones, repeating, Priority::Music));
+    Ok(())
+}
+
+/// Read a whole file, up to `MAX_FILE_SIZE`
+fn read_to_end<R: Read>(file: &mut R) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 512];
+    loop {
+        let size = file.read(&mut buffer).map_err(|_| Error::InvalidFile)?;
+        if size == 0 {
+            return Ok(data);
+        }
+        if data.len() + size > MAX_FILE_SIZE {
+            return Err(Error::TooLong);
+        }
+        data.extend_from_slice(&buffer[..size]);
+    }
+}
+
+fn start(melody: Melody) {
+    // The timer interrupt also plays the melodies
+    timer::without_interrupts(|| {
+        let mut player = PLAYER.lock();
+        player.collect();
+        player.stop();
+        player.collect();
+        player.queue(melody).expect("The queue was just emptied");
+    });
+}
+
+fn queue(melody: Melody) -> Result<()> {
+    timer::without_interrupts(|| {
+        let mut player = PLAYER.lock();
+        player.collect();
+        player.queue(melody)
+    })
+}
+
+/// Stop the current melody and empty the queue
+pub fn stop() {
+    timer::without_interrupts(|| {
+        let mut player = PLAYER.lock();
+        player.collect();
+        player.stop();
+        player.collect();
+    });
+}
+
+pub fn pause() {
+    timer::without_interrupts(|| PLAYER.lock().pause());
+}
+
+pub fn resume() {
+    timer::without_interrupts(|| PLAYER.lock().resume());
+}
+
+pub fn status() -> Status {
+    timer::without_interrupts(|| PLAYER.lock().status())
+}
+
+/// Called by the timer at the end of each tone or arpeggio step
+fn on_timer() {
+    PLAYER.lock().on_timer();
+}
+
+/// Play the frequency, or keep silent for a null one
+fn play_note(frequency: u32) {
+    if frequency == 0 {
+        disable();
+    } else {
+        enable();
+        play_frequency(frequency);
+    }
+}
+
+pub fn enable() {
+    unsafe {
+        // FIXME: This is architecture dependent and should be in arch module
+        let mut port = Port::<u8>::new(0x61);
+        let tmp = port.read();
+        port.write(tmp | 3);
+    }
+}
+
+pub fn disable() {
+    unsafe {
+        // FIXME: This is architecture dependent and should be in arch module
+        let mut port = Port::<u8>::new(0x61);
+        let tmp = port.read();
+        port.write(tmp & !3);
+    }
+}
+
+struct Player {
+    /// Melodies in queuing order
+    queue: [Option<Melody>; QUEUE_SIZE],
+    /// Finished melodies. They are freed outside of interrupts, which can't take the allocator.
+    finished: [Option<Melody>; QUEUE_SIZE],
+    /// Frequencies of the current tone, played in turn
+    chord: [u32; MAX_CHORD],
+    chord_len: usize,
+    chord_index: usize,
+    /// Uptime at which the current tone ends
+    tone_end: usize,
+    /// Remaining duration of the current tone while paused
+    paused: Option<usize>,
+    timer: Option<TimerId>,
+}
+
+impl Player {
+    const fn new() -> Self {
+        Player {
+            queue: [None, None, None, None, None, None, None, None],
+            finished: [None, None, None, None, None, None, None, None],
+            chord: [0; MAX_CHORD],
+            chord_len: 0,
+            chord_index: 0,
+            tone_end: 0,
+            paused: None,
+            timer: None,
+        }
+    }
+
+    /// Index of the melody to play: the first one with the highest priority
+    fn current(&self) -> Option<usize> {
+        self.queue
+            .iter()
+            .enumerate()
+            .filter_map(|(i, m)| m.as_ref().map(|m| (i, m.priority)))
+            .max_by_key(|&(i, priority)| (priority, Reverse(i)))
+            .map(|(i, _)| i)
+    }
+
+    /// Remove the melody and keep the others in queuing order
+    fn remove(&mut self, index: usize) {
+        let melody = self.queue[index].take();
+        for i in index..QUEUE_SIZE - 1 {
+            self.queue[i] = self.queue[i + 1].take();
+        }
+
+        // The finished melodies are collected before queuing, so together with
+        // the queue they never exceed its size and there is always a free slot.
+        if let Some(slot) = self.finished.iter_mut().find(|m| m.is_none()) {
+            *slot = melody;
+        }
+    }
+
+    /// Free the finished melodies. Must not be called from an interrupt.
+    fn collect(&mut self) {
+        for melody in self.finished.iter_mut() {
+            melody.take();
+        }
+    }
+
+    fn queue(&mut self, melody: Melody) -> Result<()> {
+        let previous = self.current();
+        let previous_priority = previous.and_then(|c| self.queue[c].as_ref()).map(|m| m.priority);
+
+        let slot = self
+            .queue
+            .iter_mut()
+            .find(|m| m.is_none())
+            .ok_or(Error::QueueFull)?;
+        let priority = melody.priority;
+        slot.replace(melody);
+
+        match previous {
+            // Nothing was playing
+            None => self.play_next_tone(),
+            Some(previous) if Some(priority) > previous_priority => {
+                // Replay the interrupted tone when the melody resumes
+                if let Some(ref mut melody) = self.queue[previous] {
+                    melody.rewind_tone();
+                }
+                self.cancel_timer();
+
+                if self.paused.is_none() {
+                    self.play_next_tone();
+                } else {
+                    // Start the new melody on resume
+                    self.chord_len = 0;
+                    self.paused = Some(0);
+                }
+            }
+            Some(_) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Play the next tone of the current melody, moving to the next melodies when it ends
+    fn play_next_tone(&mut self) {
+        while let Some(current) = self.current() {
+            let melody = self.queue[current].as_mut().expect("Current melody");
+            if let Some((chord_len, duration)) = melody.next_tone(&mut self.chord) {
+                self.chord_len = chord_len;
+                self.chord_index = 0;
+                self.tone_end = timer::uptime() + duration;
+
+                play_note(self.chord[0]);
+                self.schedule();
+                return;
+            }
+
+            self.remove(current);
+        }
+
+        self.chord_len = 0;
+        disable();
+    }
+
+    fn schedule(&mut self) {
+        let deadline = if self.chord_len > 1 {
+            min(self.tone_end, timer::uptime() + ARPEGGIO_STEP)
+        } else {
+            self.tone_end
+        };
+
+        match timer::call_at(deadline, on_timer) {
+            Ok(id) => self.timer = Some(id),
+            Err(()) => {
+                warn!("No timer left to play the melody");
+                self.stop();
+            }
+        }
+    }
+
+    fn cancel_timer(&mut self) {
+        if let Some(id) = self.timer.take() {
+            timer::cancel(id);
+        }
+    }
+
+    fn on_timer(&mut self) {
+        self.timer = None;
+
+        if timer::uptime() >= self.tone_end {
+            self.play_next_tone();
+        } else {
+            // Next note of the arpeggio
+            self.chord_index = (self.chord_index + 1) % self.chord_len;
+            play_note(self.chord[self.chord_index]);
+            self.schedule();
+        }
+    }
+
+    fn stop(&mut self) {
+        self.cancel_timer();
+        while self.queue[0].is_some() {
+            self.remove(0);
+        }
+        self.chord_len = 0;
+        self.paused = None;
+        disable();
+    }
+
+    fn pause(&mut self) {
+        if self.paused.is_some() || self.chord_len == 0 {
+            return;
+        }
+
+        self.cancel_timer();
+        self.paused = Some(self.tone_end.saturating_sub(timer::uptime()));
+        disable();
+    }
+
+    fn resume(&mut self) {
+        if let Some(remaining) = self.paused.take() {
+            if self.chord_len == 0 {
+                self.play_next_tone();
+            } else {
+                self.tone_end = timer::uptime() + remaining;
+                play_note(self.chord[self.chord_index]);
+                self.schedule();
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        let current = self.current().and_then(|c| self.queue[c].as_ref());
+
+        let state = if self.paused.is_some() {
+            State::Paused
+        } else if self.chord_len != 0 {
+            State::Playing
+        } else {
+            State::Stopped
+        };
+
+        Status {
+            state,
+            priority: current.map(|m| m.priority).unwrap_or(Priority::Music),
+            frequency: if state == State::Playing {
+                self.chord[self.chord_index]
+            } else {
+                0
+            },
+            queued: self.queue.iter().filter(|m| m.is_some()).count(),
+        }
+    }
+}
+
+struct Melody {
+    tones: Vec<Tone>,
+    index: usize,
+    /// Index of the first tone of the current chord
+    tone_start: usize,
+    repeating: bool,
+    priority: Priority,
+}
+
+impl Melody {
+    pub fn new(tones: Vec<Tone>, repeating: bool, priority: Priority) -> Self {
+        Melody {
+            tones,
+            index: 0,
+            tone_start: 0,
+            repeating,
+            priority,
+        }
+    }
+
+    /// Fill `chord` with the frequencies of the next tone.
+    /// Returns the number of frequencies and the duration, or None at the end of the melody.
+    fn next_tone(&mut self, chord: &mut [u32; MAX_CHORD]) -> Option<(usize, usize)> {
+        if self.index >= self.tones.len() {
+            if self.repeating && !self.tones.is_empty() {
+                self.index = 0
+            } else {
+                return None;
+            }
+        }
+
+        self.tone_start = self.index;
+
+        let mut len = 0;
+        while self.index < self.tones.len() {
+            let tone = &self.tones[self.index];
+            self.index += 1;
+
+            // Extra notes of a too large chord are dropped
+            if len < MAX_CHORD {
+                chord[len] = tone.frequency;
+                len += 1;
+            }
+
+            if tone.duration != 0 {
+                return Some((len, tone.duration as usize));
+            }
+        }
+
+        // The melody ends with a chord without duration, play it briefly
+        Some((len, 1))
+    }
+
+    fn rewind_tone(&mut self) {
+        self.index = self.tone_start;
+    }
+}
@@ -0,0 +1,120 @@
+use super::TimerId;
+
+#[derive(Clone, Copy)]
+pub(super) struct Timer {
+    /// Uptime at which the callback is called, in milliseconds
+    pub deadline: usize,
+    /// Interval between two calls in milliseconds, 0 for one-shot timers
+    pub period: usize,
+    pub callback: fn(),
+    pub id: TimerId,
+}
+
+impl Timer {
+    const EMPTY: Timer = Timer {
+        deadline: 0,
+        period: 0,
+        callback: nop,
+        id: TimerId(0),
+    };
+}
+
+fn nop() {}
+
+/// Min-heap of timers ordered by deadline.
+/// Its capacity is fixed, so timers can be armed from interrupts without allocating.
+pub(super) struct TimerHeap {
+    timers: [Timer; TimerHeap::CAPACITY],
+    len: usize,
+}
+
+impl TimerHeap {
+    const CAPACITY: usize = 32;
+
+    pub const fn new() -> Self {
+        TimerHeap {
+            timers: [Timer::EMPTY; TimerHeap::CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Add the timer, or return an error if the heap is full
+    pub fn push(&mut self, timer: Timer) -> Result<(), ()> {
+        if self.len == Self::CAPACITY {
+            return Err(());
+        }
+
+        self.timers[self.len] = timer;
+        self.len += 1;
+        self.sift_up(self.len - 1);
+
+        Ok(())
+    }
+
+    /// Deadline of the nearest timer
+    pub fn next_deadline(&self) -> Option<usize> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(self.timers[0].deadline)
+        }
+    }
+
+    /// Remove and return the nearest timer if its deadline is passed
+    pub fn pop_expired(&mut self, now: usize) -> Option<Timer> {
+        match self.next_deadline() {
+            Some(deadline) if deadline <= now => Some(self.remove_at(0)),
+            _ => None,
+        }
+    }
+
+    /// Remove the timer, or return None if it already expired or was removed
+    pub fn remove(&mut self, id: TimerId) -> Option<Timer> {
+        (0..self.len)
+            .find(|&i| self.timers[i].id == id)
+            .map(|i| self.remove_at(i))
+    }
+
+    fn remove_at(&mut self, index: usize) -> Timer {
+        let timer = self.timers[index];
+
+        self.len -= 1;
+        if index != self.len {
+            self.timers[index] = self.timers[self.len];
+            self.sift_down(index);
+            self.sift_up(index);
+        }
+
+        timer
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.timers[parent].deadline <= self.timers[index].deadline {
+                break;
+            }
+
+            self.timers.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let mut smallest = index;
+            for &child in [2 * index + 1, 2 * index + 2].iter() {
+                if child < self.len && self.timers[child].deadline < self.timers[smallest].deadline {
+                    smallest = child;
+                }
+            }
+
+            if smallest == index {
+                break;
+            }
+
+            self.timers.swap(smallest, index);
+            index = smallest;
+        }
+    }
+}
@@ -0,0 +1,405 @@
+#![allow(dead_code)]
+
+mod heap;
+
+use core::cmp::{max, min};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use self::heap::{Timer, TimerHeap};
+
+use crate::arch::i386::instructions::cpuid;
+use crate::arch::i386::instructions::tsc::rdtsc;
+use crate::arch::i386::pit::{Pit, PIT};
+use crate::cmdline;
+use crate::interrupts;
+
+const IRQ: u8 = 0;
+
+pub const DEFAULT_TICK_RATE: usize = 100;
+/// Bounds of the tick rate, so the PIT divisor fits in 16 bits
+const MIN_TICK_RATE: usize = 19;
+const MAX_TICK_RATE: usize = 10_000;
+/// Duration used to calibrate the TSC, in milliseconds
+const CALIBRATION_DURATION: usize = 100;
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TickMode {
+    /// The PIT interrupts at the tick rate
+    Periodic,
+    /// The PIT is programmed for the next deadline, at most at the tick rate
+    OneShot,
+}
+
+impl cmdline::Value for TickMode {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "periodic" => Some(TickMode::Periodic),
+            "oneshot" => Some(TickMode::OneShot),
+            _ => None,
+        }
+    }
+}
+
+/// Ticks per second, or maximum ticks per second in one-shot mode
+static TICK_RATE: AtomicUsize = AtomicUsize::new(DEFAULT_TICK_RATE);
+static ONE_SHOT: AtomicBool = AtomicBool::new(false);
+/// Number of PIT cycles until the next interrupt, counted from the previous one
+static PERIOD: AtomicUsize = AtomicUsize::new(0);
+/// Uptime in milliseconds, updated on each interrupt
+static UPTIME: AtomicUsize = AtomicUsize::new(0);
+/// Part of the uptime smaller than a millisecond, in thousandths of PIT cycle
+static UPTIME_FRACTION: AtomicUsize = AtomicUsize::new(0);
+static TIMERS: Mutex<TimerHeap> = Mutex::new(TimerHeap::new());
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+/// Set by the timer of the kernel sleep
+static SLEEP_DONE: AtomicBool = AtomicBool::new(false);
+
+/// Handle to cancel a timer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimerId(usize);
+/// Wall-clock time at boot, in seconds since the Unix epoch
+static BOOT_TIME: AtomicUsize = AtomicUsize::new(0);
+static TSC: Mutex<Option<Tsc>> = Mutex::new(None);
+
+/// Time stamp counter calibrated against the PIT
+#[derive(Clone, Copy, Debug)]
+struct Tsc {
+    /// Counter value at the end of the calibration
+    start: u64,
+    /// Uptime at the end of the calibration, in nanoseconds
+    start_ns: u64,
+    /// Counter increments per second
+    frequency: u64,
+}
+
+impl Tsc {
+    fn nanoseconds(&self, counter: u64) -> u64 {
+        let elapsed = counter - self.start;
+        // Split the division to avoid overflowing after a few seconds
+        self.start_ns
+            + elapsed / self.frequency * NANOSECONDS_PER_SECOND
+            + elapsed % self.frequency * NANOSECONDS_PER_SECOND / self.frequency
+    }
+}
+
+/// Program the PIT. Must be called with interrupts disabled.
+pub fn init(tick_rate: usize, mode: TickMode) {
+    let tick_rate = if tick_rate < MIN_TICK_RATE || tick_rate > MAX_TICK_RATE {
+        warn!(
+            "Tick rate {} Hz out of [{}, {}], using {} Hz",
+            tick_rate, MIN_TICK_RATE, MAX_TICK_RATE, DEFAULT_TICK_RATE
+        );
+        DEFAULT_TICK_RATE
+    } else {
+        tick_rate
+    };
+
+    info!("Timer at {} Hz in {:?} mode", tick_rate, mode);
+
+    TICK_RATE.store(tick_rate, Ordering::Release);
+    ONE_SHOT.store(mode == TickMode::OneShot, Ordering::Release);
+
+    match mode {
+        TickMode::Periodic => {
+            PERIOD.store(tick_cycles(), Ordering::Release);
+            // Prefer the local APIC timer, which needs no I/O port access on each tick
+            if interrupts::start_local_timer(tick_rate, tick).is_ok() {
+                return;
+            }
+            PIT.lock().set_rate_generator(tick_rate as u32);
+        }
+        // Only the PIT can be read back to know the uptime between interrupts
+        TickMode::OneShot => program_next_deadline(0),
+    }
+
+    interrupts::register_irq(IRQ, tick).expect("The timer IRQ is free");
+}
+
+/// Number of PIT cycles in a tick
+fn tick_cycles() -> usize {
+    Pit::FREQUENCY as usize / TICK_RATE.load(Ordering::Acquire)
+}
+
+/// Tick the clock. Should be called on each timer interrupt.
+pub fn tick() {
+    let time = advance(PERIOD.load(Ordering::Acquire));
+
+    // The terminal count is reached, so uptime() reads no remaining count and would add the
+    // period again until the PIT is programmed for the next deadline
+    if ONE_SHOT.load(Ordering::Acquire) {
+        PERIOD.store(0, Ordering::Release);
+    }
+
+    run_expired_timers(time);
+
+    if ONE_SHOT.load(Ordering::Acquire) {
+        program_next_deadline(time);
+    }
+}
+
+/// Add the elapsed PIT cycles to the uptime, and return the new uptime
+fn advance(cycles: usize) -> usize {
+    let fraction = UPTIME_FRACTION.load(Ordering::Acquire) + cycles * 1000;
+    UPTIME_FRACTION.store(fraction % Pit::FREQUENCY as usize, Ordering::Release);
+    UPTIME.fetch_add(fraction / Pit::FREQUENCY as usize, Ordering::AcqRel)
+        + fraction / Pit::FREQUENCY as usize
+}
+
+/// Call the callbacks of the expired timers, and rearm the periodic ones
+fn run_expired_timers(now: usize) {
+    loop {
+        // Release the lock before calling the callback, which may arm timers
+        let timer = {
+            let mut timers = TIMERS.lock();
+            let timer = timers.pop_expired(now);
+            if let Some(mut timer) = timer.filter(|t| t.period != 0) {
+                timer.deadline += timer.period;
+                timers.push(timer).expect("A timer was just removed");
+            }
+            timer
+        };
+
+        match timer {
+            Some(timer) => (timer.callback)(),
+            None => break,
+        }
+    }
+}
+
+/// Call `callback` from the timer interrupt once the uptime reaches `deadline`
+pub fn call_at(deadline: usize, callback: fn()) -> Result<TimerId, ()> {
+    add_timer(deadline, 0, callback)
+}
+
+/// Call `callback` from the timer interrupt every `period` milliseconds, starting at `deadline`
+pub fn call_every(deadline: usize, period: usize, callback: fn()) -> Result<TimerId, ()> {
+    assert!(period != 0, "A periodic timer needs a period");
+    add_timer(deadline, period, callback)
+}
+
+fn add_timer(deadline: usize, period: usize, callback: fn()) -> Result<TimerId, ()> {
+    let id = TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::AcqRel));
+
+    without_interrupts(|| {
+        TIMERS.lock().push(Timer {
+            deadline,
+            period,
+            callback,
+            id,
+        })
+    })?;
+
+    reschedule();
+    Ok(id)
+}
+
+/// Disarm the timer. Returns false if it already expired.
+pub fn cancel(id: TimerId) -> bool {
+    without_interrupts(|| TIMERS.lock().remove(id)).is_some()
+}
+
+/// Program the one-shot timer for the nearest deadline.
+/// The interval is at least a tick, and at most what the PIT counter can hold.
+fn program_next_deadline(now: usize) {
+    let deadline = TIMERS.lock().next_deadline();
+
+    let cycles = match deadline {
+        Some(deadline) => {
+            deadline.saturating_sub(now) * (Pit::FREQUENCY as usize / 1000)
+        }
+        None => ::core::u16::MAX as usize,
+    };
+    let cycles = min(max(cycles, tick_cycles()), ::core::u16::MAX as usize);
+
+    PERIOD.store(cycles, Ordering::Release);
+    PIT.lock().set_one_shot(cycles as u16);
+}
+
+/// Take a new deadline into account, if it comes before the programmed interrupt
+pub fn reschedule() {
+    if !ONE_SHOT.load(Ordering::Acquire) {
+        return;
+    }
+
+    without_interrupts(|| {
+        // If the terminal count is reached, the pending interrupt reschedules. If the count is not
+        // loaded yet, the deadline was programmed a moment ago.
+        let remaining = PIT.lock().read_one_shot();
+        if let Some(remaining) = remaining {
+            let elapsed = PERIOD
+                .load(Ordering::Acquire)
+                .saturating_sub(remaining as usize);
+            let now = advance(elapsed);
+            program_next_deadline(now);
+        }
+    });
+}
+
+/// Returns the uptime in milliseconds
+pub fn uptime() -> usize {
+    if !ONE_SHOT.load(Ordering::Acquire) {
+        return UPTIME.load(Ordering::Relaxed);
+    }
+
+    // Add the time elapsed since the last interrupt, which can be long
+    without_interrupts(|| {
+        let remaining = PIT.lock().read_one_shot().unwrap_or(0) as usize;
+        let cycles = PERIOD.load(Ordering::Acquire).saturating_sub(remaining);
+        let fraction = UPTIME_FRACTION.load(Ordering::Acquire) + cycles * 1000;
+        UPTIME.load(Ordering::Acquire) + fraction / Pit::FREQUENCY as usize
+    })
+}
+
+/// Halt the CPU until the uptime has advanced by `milliseconds`.
+/// Interrupts are enabled while halting, even when called from an interrupt handler.
+pub fn sleep(milliseconds: usize) {
+    let deadline = uptime() + milliseconds;
+    SLEEP_DONE.store(false, Ordering::Release);
+
+    if call_at(deadline, wake_up).is_err() {
+        // Without a free timer, wake up on each timer interrupt. Even in one-shot mode,
+        // the PIT interrupts before its 16-bit counter runs out.
+        while uptime() < deadline {
+            unsafe { llvm_asm!("sti; hlt" :::: "volatile") };
+        }
+        return;
+    }
+
+    loop {
+        // Check and halt with interrupts disabled, so the wake up can't slip in between.
+        // sti only takes effect after hlt.
+        unsafe { llvm_asm!("cli" :::: "volatile") };
+        if SLEEP_DONE.load(Ordering::Acquire) {
+            break;
+        }
+        unsafe { llvm_asm!("sti; hlt" :::: "volatile") };
+    }
+    unsafe { llvm_asm!("sti" :::: "volatile") };
+}
+
+fn wake_up() {
+    SLEEP_DONE.store(true, Ordering::Release);
+}
+
+/// Run `f` with interrupts disabled, then restore the interrupt flag
+pub fn without_interrupts<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let flags: u32;
+    unsafe { llvm_asm!("pushfl; popl $0; cli" : "=r" (flags) ::: "volatile") };
+
+    let result = f();
+
+    // Interrupt flag
+    if flags & (1 << 9) != 0 {
+        unsafe { llvm_asm!("sti" :::: "volatile") };
+    }
+
+    result
+}
+
+/// Measure the TSC frequency against the PIT. Interrupts must be enabled.
+///
+/// Without an invariant TSC, the monotonic clock keeps the PIT granularity.
+pub fn calibrate_tsc() {
+    if !cpuid::has_invariant_tsc() {
+        info!("No invariant TSC, the monotonic clock uses the PIT");
+        return;
+    }
+
+    // Start right when the uptime changes
+    let initial = uptime();
+    let mut start_time = uptime();
+    while start_time == initial {
+        start_time = uptime();
+    }
+    let start = unsafe { rdtsc() };
+
+    let mut end_time = uptime();
+    while end_time < start_time + CALIBRATION_DURATION {
+        end_time = uptime();
+    }
+    let end = unsafe { rdtsc() };
+
+    let frequency = (end - start) * 1000 / (end_time - start_time) as u64;
+    info!("TSC frequency: {} kHz", frequency / 1000);
+
+    TSC.lock().replace(Tsc {
+        start: end,
+        start_ns: end_time as u64 * 1_000_000,
+        frequency,
+    });
+}
+
+/// Returns the time since boot in nanoseconds
+pub fn monotonic_ns() -> u64 {
+    if let Some(tsc) = *TSC.lock() {
+        tsc.nanoseconds(unsafe { rdtsc() })
+    } else {
+        uptime() as u64 * 1_000_000
+    }
+}
+
+/// Set the wall-clock time, in seconds since the Unix epoch
+pub fn set_time(timestamp: usize) {
+    BOOT_TIME.store(timestamp - uptime() / 1000, Ordering::Release);
+}
+
+/// Returns the wall-clock time as seconds and nanoseconds since the Unix epoch
+pub fn time() -> (usize, usize) {
+    let monotonic = monotonic_ns();
+    (
+        BOOT_TIME.load(Ordering::Acquire) + (monotonic / NANOSECONDS_PER_SECOND) as usize,
+        (monotonic % NANOSECONDS_PER_SECOND) as usize,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uptime read by a timer callback, from the timer interrupt
+    static CALLBACK_UPTIME: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_uptime() {
+        CALLBACK_UPTIME.store(uptime(), Ordering::Release);
+    }
+
+    #[test_case]
+    fn uptime_is_monotonic() {
+        assert!(
+            ONE_SHOT.load(Ordering::Acquire),
+            "The tests run with tick_mode=oneshot"
+        );
+
+        let mut last = uptime();
+        let end = last + 100;
+        call_at(last + 10, record_uptime).unwrap();
+        let periodic = call_every(last + 15, 20, || {}).unwrap();
+
+        while last < end {
+            // Read in this order, so the callback uptime can't be more recent
+            let recorded = CALLBACK_UPTIME.load(Ordering::Acquire);
+            let now = uptime();
+            assert!(now >= last);
+            assert!(now >= recorded);
+            last = now;
+        }
+        assert!(CALLBACK_UPTIME.load(Ordering::Acquire) != 0);
+        assert!(cancel(periodic));
+    }
+
+    #[test_case]
+    fn sleep_advances_the_clocks() {
+        let (uptime_before, monotonic_before) = (uptime(), monotonic_ns());
+        sleep(20);
+
+        assert!(uptime() >= uptime_before + 20);
+        // The TSC calibration is not exact
+        assert!(monotonic_ns() > monotonic_before);
+    }
+}
@@ -1,17 +1,27 @@
 use core::cmp::max;
 use core::intrinsics::transmute;
+use core::mem::size_of;
 use core::ptr::NonNull;
 
 use elf::ElfSectionHeader;
+use no_std_io::Read;
 
-use crate::interrupts;
+use crate::backtrace;
+use crate::cmdline::{self, Param, Parameter, Value};
+use crate::fs;
+use crate::interrupts::{self, gdb};
+use crate::logger;
 use crate::memory;
 use crate::multiboot;
+use crate::peripherals::{keyboard, mouse, rtc, sb16};
 use crate::peripherals::speaker::{start_melody, Tone};
+use crate::peripherals::timer::{self, TickMode};
 use crate::peripherals::vga::{ScreenChar, TEXT_WRITER};
 use crate::ALLOCATOR;
 
-static SPLASH_SCREEN: &[ScreenChar; 2000] = unsafe {
+const SCREEN_SIZE: usize = 2000;
+
+static SPLASH_SCREEN: &[ScreenChar; SCREEN_SIZE] = unsafe {
     transmute(include_bytes!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/assets/logo-ascii.vga"
@@ -26,15 +36,102 @@ const STARTUP_MELODY: &[Tone] = &[
     Tone::new(494, 500), // Mi 3
 ];
 
-pub fn startup(infos: &multiboot::MultibootInfo) {
+/// Log levels, see the logger
+static LOG: Param<&str> = Param::new("log", "");
+static TICK_RATE: Param<usize> = Param::new("tick_rate", timer::DEFAULT_TICK_RATE);
+static TICK_MODE: Param<TickMode> = Param::new("tick_mode", TickMode::Periodic);
+/// The 8259 PIC is used if there is no APIC, or with apic=off
+static APIC: Param<bool> = Param::new("apic", true);
+static GDB: Param<gdb::Mode> = Param::new("gdb", gdb::Mode::Off);
+/// Index of the multiboot module holding the file system
+static ROOT: Param<usize> = Param::new("root", 0);
+static SPLASH: Param<Splash> = Param::new("splash", Splash::Builtin);
+static MELODY: Param<Melody> = Param::new("melody", Melody::On);
+/// Number of scans kept for the `getkey` syscall
+static KEYBOARD_BUFFER: Param<usize> = Param::new("keyboard_buffer", keyboard::DEFAULT_BUFFER_SIZE);
+
+static PARAMETERS: [&dyn Parameter; 9] = [
+    &LOG,
+    &TICK_RATE,
+    &TICK_MODE,
+    &APIC,
+    &GDB,
+    &ROOT,
+    &SPLASH,
+    &MELODY,
+    &KEYBOARD_BUFFER,
+];
+
+/// Screen displayed at boot, `splash=/path` reads it from a file of the KFS
+#[derive(Clone, Copy, Debug)]
+enum Splash {
+    Off,
+    Builtin,
+    File(&'static str),
+}
+
+impl Value for Splash {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            path if path.starts_with('/') => Some(Splash::File(path)),
+            value => match bool::parse(value)? {
+                true => Some(Splash::Builtin),
+                false => Some(Splash::Off),
+            },
+        }
+    }
+}
+
+/// Startup melody, `melody=async` does not wait for its end to continue the boot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Melody {
+    Off,
+    On,
+    Async,
+}
+
+impl Value for Melody {
+    fn parse(value: &'static str) -> Option<Self> {
+        match value {
+            "async" => Some(Melody::Async),
+            value => match bool::parse(value)? {
+                true => Some(Melody::On),
+                false => Some(Melody::Off),
+            },
+        }
+    }
+}
+
+pub fn startup(infos: &'static multiboot::MultibootInfo) {
+    cmdline::init(infos.cmdline().unwrap_or(""));
+    for &parameter in PARAMETERS.iter() {
+        cmdline::register(parameter);
+    }
+    cmdline::warn_unknown();
+    logger::set_levels(LOG.get());
+
+    backtrace::init(infos);
+
     debug!("Memory segmentation...");
     memory::segment();
     info!("Memory segmentation DONE!");
 
     debug!("Initialize interrupts...");
-    interrupts::init();
+    interrupts::init(APIC.get());
+    timer::init(TICK_RATE.get(), TICK_MODE.get());
+    keyboard::init(KEYBOARD_BUFFER.get());
+    mouse::init();
+    sb16::init();
+    gdb::init(GDB.get());
+    interrupts::enable();
     info!("Initialize interrupts DONE!");
 
+    let date = rtc::read();
+    info!("Current date: {}", date);
+    timer::set_time(date.timestamp() as usize);
+
+    timer::calibrate_tsc();
+
     let mut min_memory_addr = infos
         .mmap()
         .filter(|m| m.is_available())
@@ -77,20 +174,62 @@ pub fn startup(infos: &multiboot::MultibootInfo) {
         NonNull::new(max_memory_addr).unwrap(),
     );
 
+    // The file system holds the splash screen and the executable
+    match infos.mods().nth(ROOT.get()) {
+        Some(module) => unsafe {
+            fs::init(module.mod_start, module.mod_end).expect("fs::init failed")
+        },
+        None => warn!("No module {} detected", ROOT.get()),
+    }
+
     say_welcome();
 }
 
 fn say_welcome() {
     info!("RedK booting!");
 
+    let screen = match SPLASH.get() {
+        Splash::Off => None,
+        Splash::Builtin => Some(*SPLASH_SCREEN),
+        Splash::File(path) => read_splash_screen(path).or_else(|| {
+            warn!("Cannot read the splash screen {}, using the default one", path);
+            Some(*SPLASH_SCREEN)
+        }),
+    };
+
     let mut writer = TEXT_WRITER.lock();
     writer.disable_cursor();
 
     // Display splash screen
-    writer.write_raw(SPLASH_SCREEN);
-
-    start_melody(STARTUP_MELODY, false);
+    if let Some(screen) = screen {
+        writer.write_raw(&screen);
+    }
+    drop(writer);
+
+    let melody = MELODY.get();
+    if melody != Melody::Off {
+        start_melody(STARTUP_MELODY, false).expect("The startup melody is too long");
+    }
+
+    if melody == Melody::On {
+        let duration: u32 = STARTUP_MELODY.iter().map(|t| t.duration).sum();
+        timer::sleep(duration as usize);
+    }
+}
 
-    let duration: u32 = STARTUP_MELODY.iter().map(|t| t.duration).sum();
-    crate::peripherals::timer::sleep(duration as usize);
+/// Read a screen of raw VGA data, in the format of the builtin one
+fn read_splash_screen(path: &str) -> Option<[ScreenChar; SCREEN_SIZE]> {
+    let fs = fs::try_get()?;
+    let mut file = fs.reader(fs.find(path)?);
+
+    let mut data = [0; SCREEN_SIZE * size_of::<ScreenChar>()];
+    let mut length = 0;
+    while length < data.len() {
+        match file.read(&mut data[length..]) {
+            Ok(0) | Err(()) => return None,
+            Ok(read) => length += read,
+        }
+    }
+
+    Some(unsafe { transmute(data) })
 }
@@ -0,0 +1,61 @@
+//! In-QEMU tests, run by `make test`
+//!
+//! The test kernel runs the `#[test_case]` functions once started, then the test ROM given
+//! on the command line, which ends with the exit syscall. The results are written to the
+//! serial port, and QEMU exits with the status through the isa-debug-exit device.
+
+use core::any::type_name;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use crate::arch::i386::instructions::Port;
+use crate::backtrace;
+use crate::peripherals::serial::SERIAL_PORT;
+use crate::write_serial;
+
+/// I/O port of the isa-debug-exit device, see the Makefile
+const ISA_DEBUG_EXIT: u16 = 0xF4;
+
+/// QEMU exits with `(code << 1) | 1`, so 0 can't be used for a success
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        write_serial!("{} ... ", type_name::<T>());
+        self();
+        write_serial!("[ok]\n");
+    }
+}
+
+pub fn runner(tests: &[&dyn Testable]) {
+    write_serial!("Running {} tests\n", tests.len());
+    for test in tests {
+        test.run();
+    }
+    write_serial!("All the tests passed\n");
+}
+
+/// A failed assertion ends the tests
+pub fn panic(info: &PanicInfo) -> ! {
+    write_serial!("[failed]\n{}\n", info);
+    backtrace::print_kernel();
+    exit_qemu(ExitCode::Failure);
+}
+
+pub fn exit_qemu(code: ExitCode) -> ! {
+    unsafe { Port::<u8>::new(ISA_DEBUG_EXIT).write(code as u8) };
+
+    // Without the device, QEMU keeps running
+    loop {
+        unsafe { llvm_asm!("cli\n\thlt\n\t" :::: "volatile") };
+    }
+}
@@ -2,26 +2,66 @@ mod process;
 
 pub use self::process::*;
 
+use alloc::vec::Vec;
 use core::alloc::{GlobalAlloc, Layout};
+use core::slice;
 
 use elf::{Elf, ElfProgramHeader};
-use no_std_io::{Read, Seek, SeekFrom};
+use no_std_io::{Read, Seek};
 
+use crate::backtrace::ProgramSymbols;
 use crate::ALLOCATOR;
 
-pub fn execute_file<R>(reader: R)
+const STACK_SIZE: usize = 0x20000;
+
+#[derive(Debug)]
+pub enum Error {
+    Elf(elf::Error),
+    /// The program must be built as a PIE, to be loaded anywhere in the heap
+    NotPositionIndependent,
+    /// The program has no segment to load
+    NoSegment,
+    /// A segment is larger in the file than in memory, or has an invalid alignment
+    InvalidSegment,
+    OverlappingSegments,
+    /// The entry point is not in an executable segment
+    InvalidEntryPoint,
+    OutOfMemory,
+}
+
+/// Only returns if the program could not be loaded
+pub fn execute_file<R>(reader: R) -> Result<(), Error>
 where
-    R: Read + Seek + Clone,
+    R: Read + Seek,
 {
-    let (entry_point, base_address) = {
-        let mut elf = Elf::new(reader.clone()).unwrap();
-        let base_address = load_into_memory(&mut elf, reader);
+    // Allocate the stack first, so that the image never has to be freed here
+    let stack_layout = unsafe { Layout::from_size_align_unchecked(STACK_SIZE, 8) };
+    let stack_addr = unsafe { ALLOCATOR.alloc(stack_layout) };
+    if stack_addr.is_null() {
+        return Err(Error::OutOfMemory);
+    }
+
+    let loaded = Elf::new(reader).map_err(Error::Elf).and_then(|mut elf| {
+        let image = load_into_memory(&mut elf)?;
+        let symbols = ProgramSymbols::read(&mut elf, image.bias);
 
-        (elf.entry_point(), base_address)
+        Ok((image.bias.wrapping_add(elf.entry_point()), image, symbols))
+    });
+
+    let (entry_point, image, symbols) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            unsafe { ALLOCATOR.dealloc(stack_addr, stack_layout) };
+            return Err(e);
+        }
     };
 
-    // Allocate stack
-    let stack_addr = unsafe { ALLOCATOR.alloc(Layout::from_size_align_unchecked(0x20000, 8)) };
+    {
+        let mut process = USER_PROCESS.lock();
+        process.stack = stack_addr as usize..stack_addr as usize + STACK_SIZE;
+        process.symbols = symbols;
+        process.segments = image.segments;
+    }
 
     unsafe {
         llvm_asm!(
@@ -40,43 +80,177 @@ where
              :
              : "i" (0x20 | 0x3u16), // TODO Use (USER_DATA_SEGMENT as u16 | DPL::Ring3 as u16)
                "i" (0x18 | 0x3u16),// TODO Use (USER_CODE_SEGMENT as u16 | DPL::Ring3 as u16)
-               "{edx}" (base_address.add(entry_point)),
-               "{ebx}" (stack_addr.sub(0x1FFF8))
+               "{edx}" (entry_point),
+               "{ebx}" (stack_addr.add(STACK_SIZE - 8))
              : "a"
              : "volatile")
     };
+
+    Ok(())
+}
+
+/// Program loaded in memory
+struct Image {
+    /// Difference between the addresses in memory and the ones of the ELF
+    bias: usize,
+    segments: Vec<Segment>,
+    memory: *mut u8,
+    layout: Layout,
+}
+
+impl Image {
+    /// The image of a program which is run is never freed
+    #[allow(dead_code)]
+    fn free(self) {
+        unsafe { ALLOCATOR.dealloc(self.memory, self.layout) };
+    }
 }
 
-fn load_into_memory<R>(elf: &mut Elf<R>, mut reader: R) -> *mut u8
+/// Load the PT_LOAD segments in a single allocation, from the lowest address to the highest,
+/// and relocate them
+fn load_into_memory<R>(elf: &mut Elf<R>) -> Result<Image, Error>
 where
     R: Read + Seek,
 {
-    let max_align = elf
-        .program_headers()
-        .map(|h| h.align())
-        .max()
-        .expect("There should be at least one section");
-    let end_addr = elf
-        .program_headers()
-        .map(|h| h.vaddr() + h.mem_size())
-        .max()
-        .expect("There should be at least one section");
-
-    let base_address = unsafe {
-        ALLOCATOR
-            .alloc(Layout::from_size_align(end_addr, max_align).expect("Invalid userland layout"))
+    if !elf.is_position_independent() {
+        return Err(Error::NotPositionIndependent);
+    }
+
+    let mut headers = Vec::new();
+    for header in elf.program_headers() {
+        let header = header.map_err(Error::Elf)?;
+        if header.is_load() {
+            headers.push(header);
+        }
+    }
+    headers.sort_by_key(|h| h.vaddr());
+
+    for header in &headers {
+        if header.file_size() > header.mem_size()
+            || header.vaddr().checked_add(header.mem_size()).is_none()
+            || header.align() != 0 && !header.align().is_power_of_two()
+        {
+            return Err(Error::InvalidSegment);
+        }
+    }
+
+    if headers
+        .windows(2)
+        .any(|pair| pair[0].vaddr() + pair[0].mem_size() > pair[1].vaddr())
+    {
+        return Err(Error::OverlappingSegments);
+    }
+
+    // Checked before allocating, as nothing has to be freed yet
+    let entry_point = elf.entry_point();
+    if !headers.iter().any(|h| {
+        h.is_executable() && h.vaddr() <= entry_point && entry_point - h.vaddr() < h.mem_size()
+    }) {
+        return Err(Error::InvalidEntryPoint);
+    }
+
+    let (first, last) = match (headers.first(), headers.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(Error::NoSegment),
     };
+    let align = headers.iter().map(|h| h.align()).max().unwrap_or(1).max(1);
+    // Aligned down, so that the segments keep their alignment once moved
+    let start = first.vaddr() & !(align - 1);
+    let end = last.vaddr() + last.mem_size();
+    if end == start {
+        return Err(Error::NoSegment);
+    }
+
+    let layout = Layout::from_size_align(end - start, align).map_err(|_| Error::InvalidSegment)?;
+    let memory = unsafe { ALLOCATOR.alloc(layout) };
+    if memory.is_null() {
+        return Err(Error::OutOfMemory);
+    }
+
+    let bias = (memory as usize).wrapping_sub(start);
+    let loaded = copy_segments(elf, &headers, bias).and_then(|segments| {
+        let image = unsafe { slice::from_raw_parts_mut(memory, layout.size()) };
+        elf.relocate(image, start, bias).map_err(Error::Elf)?;
+        Ok(segments)
+    });
+
+    match loaded {
+        Ok(segments) => Ok(Image {
+            bias,
+            segments,
+            memory,
+            layout,
+        }),
+        Err(e) => {
+            unsafe { ALLOCATOR.dealloc(memory, layout) };
+            Err(e)
+        }
+    }
+}
+
+/// Copy the content of the segments, and zero their end which is not in the file (the BSS)
+fn copy_segments<R, H>(elf: &mut Elf<R>, headers: &[H], bias: usize) -> Result<Vec<Segment>, Error>
+where
+    R: Read + Seek,
+    H: ElfProgramHeader,
+{
+    let mut segments = Vec::with_capacity(headers.len());
+
+    for header in headers {
+        let address = bias.wrapping_add(header.vaddr());
+        let memory = unsafe { slice::from_raw_parts_mut(address as *mut u8, header.mem_size()) };
+        let size = elf.read_segment(header, memory).map_err(Error::Elf)?;
 
-    for segment in elf.program_headers() {
-        reader.seek(SeekFrom::Start(segment.offset())).unwrap();
-        let memory = unsafe {
-            ::core::slice::from_raw_parts_mut(
-                base_address.add(segment.vaddr()),
-                segment.file_size(),
-            )
+        for byte in memory[size..].iter_mut() {
+            *byte = 0;
+        }
+
+        let segment = Segment {
+            memory: address..address + header.mem_size(),
+            readable: header.is_readable(),
+            writable: header.is_writable(),
+            executable: header.is_executable(),
         };
-        reader.read(memory).unwrap();
+        debug!("Loaded {:X?}", segment);
+        segments.push(segment);
     }
 
-    base_address
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs;
+
+    #[test_case]
+    fn read_test_rom() {
+        let fs = fs::try_get().expect("The tests need a KFS module");
+        let reader = fs.reader(fs.find("/ktest").expect("ktest"));
+
+        let mut elf = Elf::new(reader).expect("ktest should be an ELF");
+        assert!(elf
+            .program_headers()
+            .any(|h| h.map_or(false, |h| h.is_load())));
+        assert!(ProgramSymbols::read(&mut elf, 0).is_some());
+    }
+
+    #[test_case]
+    fn load_test_rom() {
+        let fs = fs::try_get().expect("The tests need a KFS module");
+        let reader = fs.reader(fs.find("/ktest").expect("ktest"));
+
+        let mut elf = Elf::new(reader).expect("ktest should be an ELF");
+        let image = load_into_memory(&mut elf).expect("ktest should load");
+        assert!(image.segments.iter().any(|s| s.executable && !s.writable));
+        assert!(image.segments.iter().any(|s| s.writable && !s.executable));
+
+        let entry_point = image.bias.wrapping_add(elf.entry_point());
+        assert!(image
+            .segments
+            .iter()
+            .any(|s| s.memory.contains(&entry_point)));
+
+        image.free();
+    }
 }
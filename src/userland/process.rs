@@ -1,10 +1,12 @@
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::ops::Range;
 
+use kfs::FileHandle;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use crate::kfs::FileHandle;
+use crate::backtrace::ProgramSymbols;
 
 lazy_static! {
     pub static ref USER_PROCESS: Mutex<Process> = Mutex::new(Process::new());
@@ -14,6 +16,20 @@ pub struct Process {
     file_descriptors: [Option<Box<dyn FileHandle + Sync + Send + 'static>>; Process::MAX_FD],
     pub memory: Vec<u8>,
     pub brk: usize,
+    /// Bounds of the stack, where the frames of a backtrace are
+    pub stack: Range<usize>,
+    pub symbols: Option<ProgramSymbols>,
+    /// Segments of the program, with their permissions
+    pub segments: Vec<Segment>,
+}
+
+/// Loaded segment of the program
+#[derive(Debug)]
+pub struct Segment {
+    pub memory: Range<usize>,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
 }
 
 impl Process {
@@ -34,6 +50,9 @@ impl Process {
             ],
             memory: Vec::with_capacity(Self::MEMORY_SIZE),
             brk: 0,
+            stack: 0..0,
+            symbols: None,
+            segments: Vec::new(),
         };
         process.memory.push(0);
         process
@@ -64,6 +83,37 @@ impl Process {
         }
     }
 
+    /// Without paging, the permissions are only enforced when the kernel accesses memory for
+    /// the program
+    pub fn can_write(&self, address: usize, size: usize) -> bool {
+        self.is_accessible(address, size, |s| s.writable)
+    }
+
+    pub fn can_read(&self, address: usize, size: usize) -> bool {
+        self.is_accessible(address, size, |s| s.readable)
+    }
+
+    /// The memory must lie in a segment with the `permission`, the stack or the memory of `sbrk`
+    fn is_accessible(&self, address: usize, size: usize, permission: fn(&Segment) -> bool) -> bool {
+        let end = match address.checked_add(size) {
+            Some(end) => end,
+            None => return false,
+        };
+        let contains = |memory: &Range<usize>| memory.start <= address && end <= memory.end;
+
+        self.segments
+            .iter()
+            .any(|s| permission(s) && contains(&s.memory))
+            || contains(&self.stack)
+            || contains(&self.heap())
+    }
+
+    /// Memory given by `sbrk`, which returns addresses up to its capacity
+    fn heap(&self) -> Range<usize> {
+        let start = self.memory.as_ptr() as usize;
+        start..start + self.memory.capacity()
+    }
+
     /// Remove the fd if any
     pub fn close_file(&mut self, fd: u32) -> Result<(), ()> {
         let fd = fd as usize;
@@ -82,3 +132,42 @@ impl Default for Process {
         Process::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn can_write() {
+        let mut process = Process::new();
+        process.stack = 0x1000..0x2000;
+        process.segments.push(Segment {
+            memory: 0x3000..0x4000,
+            readable: true,
+            writable: false,
+            executable: true,
+        });
+        process.segments.push(Segment {
+            memory: 0x4000..0x5000,
+            readable: true,
+            writable: true,
+            executable: false,
+        });
+
+        assert!(process.can_write(0x1000, 0x1000));
+        assert!(process.can_write(0x4800, 0x10));
+        let heap = process.memory.as_ptr() as usize;
+        assert!(process.can_write(heap, 1));
+
+        // Read-only segment, across two segments, or outside of the program
+        assert!(!process.can_write(0x3800, 0x10));
+        assert!(!process.can_write(0x3FF0, 0x20));
+        assert!(!process.can_write(0x4FF0, 0x20));
+        assert!(!process.can_write(0x100, 0x10));
+        assert!(!process.can_write(::core::usize::MAX, 2));
+
+        assert!(process.can_read(0x3800, 0x10));
+        assert!(!process.can_read(0x3FF0, 0x20));
+        assert!(!process.can_read(0x100, 0x10));
+    }
+}
@@ -1,25 +1,40 @@
 #!/bin/sh
 
+# Usage: create-iso.sh ISO KERNEL ROM...
+# Each ROM gets an entry in the boot menu. KERNEL_OPTIONS are added to the
+# command line of the kernel, and GRUB_TIMEOUT sets the delay before booting
+# the first entry.
+
 iso_filename=$1
 kernel_filename=$2
-base_dir=build/iso
+shift 2
+base_dir=build/$(basename $iso_filename .iso)
 
+rm -rf $base_dir/roms/
 mkdir -p $base_dir/
 mkdir -p $base_dir/roms/
 mkdir -p $base_dir/boot/grub/
 
 cp $kernel_filename $base_dir/boot/kernel.bin
 
-find roms -name "*.rom" -exec cp {} $base_dir/roms/ \;
+for rom in "$@" ; do
+	cp $rom $base_dir/roms/
+done
+
+{
+	if [ -n "$GRUB_TIMEOUT" ]; then
+		echo "set timeout=$GRUB_TIMEOUT"
+	fi
 
-for rom in $(find $base_dir/roms -name "*.rom") ; do
-	name=$(basename $rom .rom)
-	cat <<EOF
+	for rom in $(find $base_dir/roms -name "*.rom") ; do
+		name=$(basename $rom .rom)
+		cat <<EOF
 menuentry "k - $name" {
-	multiboot /boot/kernel.bin /$name
+	multiboot /boot/kernel.bin /$name $KERNEL_OPTIONS
 	module /roms/$name.rom
 }
 EOF
-done > $base_dir/boot/grub/grub.cfg
+	done
+} > $base_dir/boot/grub/grub.cfg
 
 grub-mkrescue -o $iso_filename $base_dir
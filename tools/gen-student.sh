@@ -7,7 +7,6 @@ roms/chichehunter/ \
 roms/chichepong/ \
 roms/chichevaders/ \
 roms/perrodlauncher/ \
-roms/roms.lds \
 roms/roms.mk \
 roms/skate/ \
 roms/yakanoid/ \
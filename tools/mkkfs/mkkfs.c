@@ -273,5 +273,9 @@ int main(int argc, char **argv)
 
 	kfs_write_superblock(romfd, rom_name, blk_cnt, nb_files);
 
+	/* the last block may be an indirect one, smaller than a block */
+	if (ftruncate(romfd, (off_t)blk_cnt * KFS_BLK_SZ) < 0)
+		err(1, "unable to pad %s", rom_file);
+
 	return 0;
 }